@@ -44,15 +44,14 @@ pub fn run<E: Example + 'static>() {
                 input,
                 is_synthetic: _,
             } => {
-                if let Some(key_code) = input.virtual_keycode {
-                    match key_code {
-                        VirtualKeyCode::Escape => {
-                            *control_flow = winit::event_loop::ControlFlow::Exit
-                        }
-                        _ => {}
-                    }
+                if let Some(VirtualKeyCode::Escape) = input.virtual_keycode {
+                    *control_flow = winit::event_loop::ControlFlow::Exit
                 }
             }
+            WindowEvent::Resized(size) => ctx.resize([size.width, size.height]),
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                ctx.resize([new_inner_size.width, new_inner_size.height])
+            }
             WindowEvent::CloseRequested => *control_flow = winit::event_loop::ControlFlow::Exit,
             _ => {}
         },
@@ -35,7 +35,8 @@ impl common::Example for DrawCanvasExample {
         self.text_hidden.draw(
             render,
             Transform {
-                position: [0.5, 0.5].into(),
+                position: [32.0, 32.0].into(),
+                scale: [0.4, 0.4].into(),
                 ..Default::default()
             },
         );
@@ -46,7 +47,8 @@ impl common::Example for DrawCanvasExample {
         self.text_shown.draw(
             render,
             Transform {
-                position: [0.5, 0.5].into(),
+                position: [32.0, 32.0].into(),
+                scale: [0.4, 0.4].into(),
                 ..Default::default()
             },
         );
@@ -13,7 +13,7 @@ impl common::Example for DrawImageBatchExample {
         let image_data = image::load_from_memory(image_bytes).unwrap();
         let image_rgba = image_data.as_rgba8().unwrap();
         let dimensions = image_data.dimensions();
-        let mut batch = ImageBatch::new(ctx, [dimensions.0, dimensions.1], image_rgba);
+        let mut batch = ImageBatch::new(ctx, [dimensions.0, dimensions.1], image_rgba).unwrap();
         batch.add_instance(
             ctx,
             &[Transform {
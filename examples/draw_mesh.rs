@@ -44,7 +44,7 @@ fn create_mesh(ctx: &GraphicsContext) -> Mesh {
             oblivion::DrawMode::stroke(0.02),
         )
         .unwrap()
-        .build(&ctx)
+        .build(ctx)
 }
 
 impl common::Example for DrawMeshExample {
@@ -38,7 +38,7 @@ impl common::Example for DrawMeshExample {
                 )
                 .unwrap();
         }
-        let mesh = builder.build(&ctx);
+        let mesh = builder.build(ctx);
         DrawMeshExample { mesh }
     }
 
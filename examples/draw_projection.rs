@@ -27,7 +27,7 @@ impl common::Example for DrawProjectionExample {
                 oblivion::DrawMode::stroke(dimensions.x / 100.0),
             )
             .unwrap()
-            .build(&ctx);
+            .build(ctx);
         let mut text = Text::new(ctx);
         text.add_text(["Projection Test!"]);
         text.flush(ctx);
@@ -35,7 +35,7 @@ impl common::Example for DrawProjectionExample {
         let image_data = image::load_from_memory(image_bytes).unwrap();
         let image_rgba = image_data.as_rgba8().unwrap();
         let img_dimensions = image_data.dimensions();
-        let image = Image::new(ctx, [img_dimensions.0, img_dimensions.1], image_rgba);
+        let image = Image::new(ctx, [img_dimensions.0, img_dimensions.1], image_rgba).unwrap();
         DrawProjectionExample {
             mesh,
             text,
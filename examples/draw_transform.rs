@@ -16,7 +16,7 @@ impl common::Example for DrawImageExample {
         let image_data = image::load_from_memory(image_bytes).unwrap();
         let image_rgba = image_data.as_rgba8().unwrap();
         let dimensions = image_data.dimensions();
-        let image = Image::new(ctx, [dimensions.0, dimensions.1], image_rgba);
+        let image = Image::new(ctx, [dimensions.0, dimensions.1], image_rgba).unwrap();
         DrawImageExample {
             image,
             start: Instant::now(),
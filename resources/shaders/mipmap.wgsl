@@ -0,0 +1,27 @@
+// Downsamples the previous mip level into the next one.
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A single triangle covering the whole target.
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.uv = uv;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+[[group(0), binding(0)]]
+var t_source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_source: sampler;
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(t_source, s_source, in.uv);
+}
@@ -11,10 +11,14 @@ struct InstanceInput {
     [[location(6)]] matrix_1: vec4<f32>;
     [[location(7)]] matrix_2: vec4<f32>;
     [[location(8)]] matrix_3: vec4<f32>;
+    [[location(9)]] src: vec4<f32>;
+    [[location(10)]] color: vec4<f32>;
 };
 
 struct Uniform {
     mvp: mat4x4<f32>;
+    // Tint of draws that use their own instance buffer
+    color: vec4<f32>;
 };
 [[group(1), binding(0)]]
 var<uniform> uni: Uniform;
@@ -38,8 +42,8 @@ fn vs_main(
     );
 
     var out: VertexOutput;
-    out.color = model.color;
-    out.uv = model.uv;
+    out.color = model.color * instance.color * uni.color;
+    out.uv = instance.src.xy + model.uv * instance.src.zw;
     let position = instance_matrix * vec4<f32>(model.position, 0.0, 1.0);
     let out_pos = uni.mvp * position;
     out.clip_position = out_pos;
@@ -53,9 +57,20 @@ var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_diffuse: sampler;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+fn fragment_color(in: VertexOutput) -> vec4<f32> {
     return in.color * textureSample(t_diffuse, s_diffuse, in.uv);
     //return textureSample(t_diffuse, s_diffuse, in.uv);
     //return vec4<f32>(in.color, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return fragment_color(in);
+}
+
+// Used by blend modes that expect premultiplied color
+[[stage(fragment)]]
+fn fs_premultiplied(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = fragment_color(in);
+    return vec4<f32>(color.rgb * color.a, color.a);
 }
\ No newline at end of file
@@ -11,10 +11,13 @@ struct InstanceInput {
     [[location(6)]] matrix_1: vec4<f32>;
     [[location(7)]] matrix_2: vec4<f32>;
     [[location(8)]] matrix_3: vec4<f32>;
+    [[location(10)]] color: vec4<f32>;
 };
 
 struct Uniform {
     mvp: mat4x4<f32>;
+    // Tint of draws that use their own instance buffer
+    color: vec4<f32>;
 };
 [[group(1), binding(0)]]
 var<uniform> uni: Uniform;
@@ -38,7 +41,7 @@ fn vs_main(
     );
 
     var out: VertexOutput;
-    out.color = model.color;
+    out.color = model.color * instance.color * uni.color;
     out.uv = model.uv;
     let position = instance_matrix * vec4<f32>(model.position, 0.0, 1.0);
     let out_pos = uni.mvp * position;
@@ -53,9 +56,20 @@ var t_diffuse: texture_2d<f32>;
 [[group(0), binding(1)]]
 var s_diffuse: sampler;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+fn fragment_color(in: VertexOutput) -> vec4<f32> {
     // There's not A8Unorm texture format so we have to make it ourselves
     return in.color * vec4<f32>(1.0, 1.0, 1.0, textureSample(t_diffuse, s_diffuse, in.uv).r);
     //return vec4<f32>(in.color, 1.0) * textureSample(t_diffuse, s_diffuse, in.uv);
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return fragment_color(in);
+}
+
+// Used by blend modes that expect premultiplied color
+[[stage(fragment)]]
+fn fs_premultiplied(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = fragment_color(in);
+    return vec4<f32>(color.rgb * color.a, color.a);
 }
\ No newline at end of file
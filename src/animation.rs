@@ -0,0 +1,433 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{Image, Rect, Render, Transform};
+
+/// What an animation does once it reaches its last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoopMode {
+    /// Stops on the last frame.
+    Once,
+    /// Starts over from the first frame.
+    #[default]
+    Loop,
+    /// Plays backwards to the first frame, then forwards again.
+    PingPong,
+}
+
+/// A single frame of an animation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationFrame {
+    /// Index of the frame in the sprite sheet.
+    pub index: usize,
+    /// How long the frame is shown, in seconds.
+    pub duration: f32,
+}
+
+/// A sequence of sprite sheet frames, such as a walk cycle.
+///
+/// Example usage:
+/// ```rust
+/// let walk = Animation::uniform(0..4, 0.1, LoopMode::Loop);
+/// let jump = Animation::new(LoopMode::Once)
+///     .with_frame(4, 0.05)
+///     .with_frame(5, 0.3);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+    pub mode: LoopMode,
+}
+
+impl Animation {
+    /// Creates an animation without frames.
+    pub fn new(mode: LoopMode) -> Self {
+        Animation {
+            frames: Vec::new(),
+            mode,
+        }
+    }
+
+    /// Creates an animation where every frame is shown equally long.
+    pub fn uniform(frames: impl IntoIterator<Item = usize>, duration: f32, mode: LoopMode) -> Self {
+        Animation {
+            frames: frames
+                .into_iter()
+                .map(|index| AnimationFrame { index, duration })
+                .collect(),
+            mode,
+        }
+    }
+
+    /// Adds a frame to the end of the animation.
+    pub fn with_frame(mut self, index: usize, duration: f32) -> Self {
+        self.frames.push(AnimationFrame { index, duration });
+        self
+    }
+
+    /// Time it takes to play the animation once, ping-pong animations take this long for each direction.
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+
+    /// Whether an animation that started `time` seconds ago has stopped, this is only possible with `LoopMode::Once`.
+    pub fn is_finished(&self, time: f32) -> bool {
+        self.mode == LoopMode::Once && time >= self.duration()
+    }
+
+    /// Gets the sprite sheet frame shown `time` seconds after the animation started.
+    pub fn frame_at(&self, time: f32) -> Option<usize> {
+        let last = self.frames.last()?;
+        let duration = self.duration();
+        if duration <= 0.0 {
+            return Some(last.index);
+        }
+        let time = time.max(0.0);
+        match self.mode {
+            LoopMode::Once if time >= duration => Some(last.index),
+            LoopMode::Once => frame_in(self.frames.iter(), time),
+            LoopMode::Loop => frame_in(self.frames.iter(), time % duration),
+            LoopMode::PingPong => {
+                // The first and last frames aren't repeated when turning around.
+                let inner = &self.frames[1..self.frames.len().saturating_sub(1).max(1)];
+                let cycle = duration + inner.iter().map(|frame| frame.duration).sum::<f32>();
+                frame_in(self.frames.iter().chain(inner.iter().rev()), time % cycle)
+            }
+        }
+    }
+}
+
+/// Finds the frame shown `time` seconds into a sequence of frames.
+fn frame_in<'a>(frames: impl Iterator<Item = &'a AnimationFrame>, mut time: f32) -> Option<usize> {
+    let mut current = None;
+    for frame in frames {
+        current = Some(frame.index);
+        if time < frame.duration {
+            break;
+        }
+        time -= frame.duration;
+    }
+    current
+}
+
+/// An image divided into frames, with named animations using those frames.
+/// Cloning is cheap, the frames and animations are shared.
+///
+/// Example usage:
+/// ```rust
+/// let mut sheet = SpriteSheet::from_grid(image, [32, 32]);
+/// sheet.add_animation("walk", Animation::uniform(0..4, 0.1, LoopMode::Loop));
+/// let mut player = AnimationPlayer::new(sheet);
+/// player.play("walk");
+/// /* ... */
+/// player.update(dt);
+/// player.draw(&mut render, Transform::default());
+/// ```
+#[derive(Clone)]
+pub struct SpriteSheet {
+    image: Image,
+    frames: Rc<Vec<Rect>>,
+    animations: Rc<HashMap<String, Animation>>,
+}
+
+impl SpriteSheet {
+    /// Creates a sprite sheet from frame rectangles in texture pixels.
+    pub fn from_rects(image: Image, frames: Vec<Rect>) -> Self {
+        SpriteSheet {
+            image,
+            frames: Rc::new(frames),
+            animations: Default::default(),
+        }
+    }
+
+    /// Creates a sprite sheet by dividing the image into equally sized frames, numbered left to right, top to bottom.
+    pub fn from_grid(image: Image, frame_dimensions: impl Into<mint::Vector2<u32>>) -> Self {
+        let frame_dimensions = frame_dimensions.into();
+        let columns = image.tex_dim.x as u32 / frame_dimensions.x;
+        let rows = image.tex_dim.y as u32 / frame_dimensions.y;
+        let frames = (0..rows)
+            .flat_map(|row| {
+                (0..columns).map(move |column| {
+                    Rect::new(
+                        (column * frame_dimensions.x) as f32,
+                        (row * frame_dimensions.y) as f32,
+                        frame_dimensions.x as f32,
+                        frame_dimensions.y as f32,
+                    )
+                })
+            })
+            .collect();
+        Self::from_rects(image, frames)
+    }
+
+    /// Adds a named animation, replacing any animation with the same name.
+    pub fn add_animation(&mut self, name: impl Into<String>, animation: Animation) -> &mut Self {
+        Rc::make_mut(&mut self.animations).insert(name.into(), animation);
+        self
+    }
+
+    /// Gets a named animation.
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    /// Gets the rectangle of a frame in texture pixels.
+    pub fn frame(&self, index: usize) -> Option<Rect> {
+        self.frames.get(index).copied()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Gets the image the frames are taken from.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Pushes a single frame to the draw queue, nothing is drawn if the frame doesn't exist.
+    pub fn draw_frame(&self, render: &mut Render, index: usize, transform: Transform) {
+        if let Some(src) = self.frame(index) {
+            self.image.draw_region(render, src, transform);
+        }
+    }
+
+    /// Creates a sprite sheet from the JSON exported by Aseprite or TexturePacker, see `SpriteSheetLayout::from_json`.
+    #[cfg(feature = "json")]
+    pub fn from_json(image: Image, json: &str) -> crate::OblivionResult<Self> {
+        Ok(Self::from_layout(
+            image,
+            SpriteSheetLayout::from_json(json)?,
+        ))
+    }
+
+    /// Creates a sprite sheet from frames and animations that were loaded separately.
+    pub fn from_layout(image: Image, layout: SpriteSheetLayout) -> Self {
+        SpriteSheet {
+            image,
+            frames: Rc::new(layout.frames),
+            animations: Rc::new(layout.animations),
+        }
+    }
+}
+
+/// The frames and animations of a sprite sheet, without the image they're taken from.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SpriteSheetLayout {
+    /// Frame rectangles in texture pixels.
+    pub frames: Vec<Rect>,
+    pub animations: HashMap<String, Animation>,
+}
+
+impl SpriteSheetLayout {
+    /// Parses the JSON exported by Aseprite or TexturePacker, in either the hash or array layout.
+    /// Frames are numbered in the order they appear in the file and Aseprite tags become animations.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> crate::OblivionResult<Self> {
+        let sheet: json::SheetJson = serde_json::from_str(json)?;
+        let frames = sheet
+            .frames
+            .0
+            .iter()
+            .map(|frame| Rect::new(frame.frame.x, frame.frame.y, frame.frame.w, frame.frame.h))
+            .collect::<Vec<_>>();
+        let durations = sheet
+            .frames
+            .0
+            .iter()
+            .map(|frame| frame.duration.unwrap_or(100.0) / 1000.0)
+            .collect::<Vec<_>>();
+        let mut animations = HashMap::new();
+        for tag in sheet.meta.map(|meta| meta.frame_tags).unwrap_or_default() {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(crate::OblivionError::InvalidSpriteSheetTag {
+                    name: tag.name,
+                    from: tag.from,
+                    to: tag.to,
+                    frame_count: frames.len(),
+                });
+            }
+            let mut indices = (tag.from..=tag.to).collect::<Vec<_>>();
+            if matches!(tag.direction.as_str(), "reverse" | "pingpong_reverse") {
+                indices.reverse();
+            }
+            let mode = if tag.direction.starts_with("pingpong") {
+                LoopMode::PingPong
+            } else {
+                LoopMode::Loop
+            };
+            let animation = Animation {
+                frames: indices
+                    .into_iter()
+                    .map(|index| AnimationFrame {
+                        index,
+                        duration: durations[index],
+                    })
+                    .collect(),
+                mode,
+            };
+            animations.insert(tag.name, animation);
+        }
+        Ok(SpriteSheetLayout { frames, animations })
+    }
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use serde::{
+        de::{MapAccess, SeqAccess, Visitor},
+        Deserialize, Deserializer,
+    };
+
+    #[derive(Deserialize)]
+    pub struct SheetJson {
+        pub frames: FramesJson,
+        pub meta: Option<MetaJson>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct FrameJson {
+        pub frame: RectJson,
+        /// In milliseconds, only exported by Aseprite.
+        pub duration: Option<f32>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct RectJson {
+        pub x: f32,
+        pub y: f32,
+        pub w: f32,
+        pub h: f32,
+    }
+
+    #[derive(Deserialize)]
+    pub struct MetaJson {
+        #[serde(rename = "frameTags", default)]
+        pub frame_tags: Vec<TagJson>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TagJson {
+        pub name: String,
+        pub from: usize,
+        pub to: usize,
+        #[serde(default)]
+        pub direction: String,
+    }
+
+    /// Frames as either an array or an object keyed by file name, kept in file order.
+    pub struct FramesJson(pub Vec<FrameJson>);
+
+    impl<'de> Deserialize<'de> for FramesJson {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct FramesVisitor;
+
+            impl<'de> Visitor<'de> for FramesVisitor {
+                type Value = FramesJson;
+
+                fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    formatter.write_str("an array or map of frames")
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<FramesJson, A::Error> {
+                    let mut frames = Vec::new();
+                    while let Some(frame) = seq.next_element()? {
+                        frames.push(frame);
+                    }
+                    Ok(FramesJson(frames))
+                }
+
+                fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FramesJson, A::Error> {
+                    let mut frames = Vec::new();
+                    while let Some((_, frame)) = map.next_entry::<String, FrameJson>()? {
+                        frames.push(frame);
+                    }
+                    Ok(FramesJson(frames))
+                }
+            }
+
+            deserializer.deserialize_any(FramesVisitor)
+        }
+    }
+}
+
+/// Plays the animations of a sprite sheet.
+#[derive(Clone)]
+pub struct AnimationPlayer {
+    sheet: SpriteSheet,
+    animation: Option<String>,
+    time: f32,
+    /// Multiplier for the time passed to `update`.
+    pub speed: f32,
+    /// Whether `update` advances the animation.
+    pub playing: bool,
+}
+
+impl AnimationPlayer {
+    /// Creates a player that isn't playing any animation yet.
+    pub fn new(sheet: SpriteSheet) -> Self {
+        AnimationPlayer {
+            sheet,
+            animation: None,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+        }
+    }
+
+    /// Switches to a named animation, restarting only if it wasn't already playing.
+    pub fn play(&mut self, name: &str) {
+        if self.animation.as_deref() != Some(name) {
+            self.restart(name);
+        }
+    }
+
+    /// Plays a named animation from the start.
+    pub fn restart(&mut self, name: &str) {
+        self.animation = Some(name.to_owned());
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    /// Advances the animation by `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        if self.playing {
+            self.time += dt * self.speed;
+        }
+    }
+
+    /// Gets the name of the current animation.
+    pub fn animation(&self) -> Option<&str> {
+        self.animation.as_deref()
+    }
+
+    /// Seconds since the current animation started.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Whether the current animation has stopped on its last frame.
+    pub fn is_finished(&self) -> bool {
+        self.current_animation()
+            .is_some_and(|animation| animation.is_finished(self.time))
+    }
+
+    /// Gets the sprite sheet frame that is currently shown.
+    pub fn current_frame(&self) -> Option<usize> {
+        self.current_animation()?.frame_at(self.time)
+    }
+
+    pub fn sheet(&self) -> &SpriteSheet {
+        &self.sheet
+    }
+
+    fn current_animation(&self) -> Option<&Animation> {
+        self.sheet.animation(self.animation.as_deref()?)
+    }
+
+    /// Pushes the current frame to the draw queue, nothing is drawn without an animation.
+    pub fn draw(&self, render: &mut Render, transform: Transform) {
+        if let Some(index) = self.current_frame() {
+            self.sheet.draw_frame(render, index, transform);
+        }
+    }
+}
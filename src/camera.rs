@@ -0,0 +1,76 @@
+use crate::{Angle, GraphicsContext};
+
+/// A view into the world that can be panned, zoomed and rotated.
+///
+/// Example usage:
+/// ```rust
+/// let mut camera = Camera::new([0.5, 0.5]);
+/// camera.zoom = 2.0;
+/// oblivion::push_camera(&mut render, camera);
+/// /* ... */
+/// oblivion::pop_camera(&mut render);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// World position shown at the center of the view.
+    pub position: mint::Point2<f32>,
+    /// Scale of the world, values above 1.0 zoom in.
+    pub zoom: f32,
+    /// Rotation of the view around its center.
+    pub rotation: Angle,
+}
+
+impl Camera {
+    /// Creates a new camera centered on a world position.
+    pub fn new(position: impl Into<mint::Point2<f32>>) -> Self {
+        Camera {
+            position: position.into(),
+            zoom: 1.0,
+            rotation: Angle::from_radians(0.0),
+        }
+    }
+
+    /// Maps world positions to render positions.
+    pub(crate) fn view_matrix(&self, render_dimensions: mint::Vector2<f32>) -> glam::Mat4 {
+        let center = glam::Mat4::from_translation(glam::vec3(
+            render_dimensions.x / 2.0,
+            render_dimensions.y / 2.0,
+            0.0,
+        ));
+        let rotation = glam::Mat4::from_rotation_z(-self.rotation.rad());
+        let zoom = glam::Mat4::from_scale(glam::vec3(self.zoom, self.zoom, 1.0));
+        let translate =
+            glam::Mat4::from_translation(glam::vec3(-self.position.x, -self.position.y, 0.0));
+        center * rotation * zoom * translate
+    }
+
+    /// Converts a position in surface pixels, such as the mouse position, to a world position.
+    pub fn screen_to_world(
+        &self,
+        ctx: &GraphicsContext,
+        point: impl Into<mint::Point2<f32>>,
+    ) -> mint::Point2<f32> {
+        let point = point.into();
+        let norm_vec = ctx.normalization_vector();
+        let view = self.view_matrix(ctx.gfx_config.render_dimensions);
+        let world = view.inverse().transform_point3(glam::vec3(
+            point.x * norm_vec.x,
+            point.y * norm_vec.y,
+            0.0,
+        ));
+        [world.x, world.y].into()
+    }
+
+    /// Converts a world position to a position in surface pixels.
+    pub fn world_to_screen(
+        &self,
+        ctx: &GraphicsContext,
+        point: impl Into<mint::Point2<f32>>,
+    ) -> mint::Point2<f32> {
+        let point = point.into();
+        let norm_vec = ctx.normalization_vector();
+        let view = self.view_matrix(ctx.gfx_config.render_dimensions);
+        let render = view.transform_point3(glam::vec3(point.x, point.y, 0.0));
+        [render.x / norm_vec.x, render.y / norm_vec.y].into()
+    }
+}
@@ -1,13 +1,23 @@
-use std::{num::NonZeroU64, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    num::{NonZeroU32, NonZeroU64},
+    rc::Rc,
+};
 
 use glyph_brush::{GlyphBrush, GlyphBrushBuilder};
 use wgpu::util::DeviceExt;
 
 use crate::{
-    helpers::{create_pipeline, get_adapter_surface, get_device_queue},
-    internal::PipelineData,
-    DrawData, Font, MeshBuffer, OblivionError, OblivionResult, Render, RenderData, RenderGroup,
-    TargetId, Transform, Vertex, QUAD_INDICES, QUAD_VERTICES,
+    helpers::{
+        create_mipmap_pipeline, create_pipeline, create_pipeline_layout, create_shader_pipelines,
+        download_texture, get_adapter_surface, get_device_queue, get_headless_adapter,
+    },
+    BlendMode, CanvasTarget, DrawBatch, DrawData, Font, Handle, InstanceType, MeshBuffer,
+    OblivionError, OblivionResult, PipelineVariant, Rect, ReleaseQueue, Render, RenderData,
+    RenderGroup, SamplerOptions, ShaderPipelines, Store, StoreHandle, TargetId, Transform, Vertex,
+    FULL_SRC, INSTANCE_SIZE, QUAD_INDICES, QUAD_VERTICES, STANDARD_PIPELINE, STENCIL_FORMAT,
+    TEXT_PIPELINE, WHITE,
 };
 
 type UniformType = [[f32; 4]; 4];
@@ -18,24 +28,42 @@ pub struct GraphicsConfig {
     pub render_dimensions: mint::Vector2<f32>,
 }
 
+/// Where `TargetId::Screen` is rendered to.
+pub(crate) enum ScreenTarget {
+    Surface(wgpu::Surface),
+    Offscreen(wgpu::Texture),
+}
+
 /// Context for graphics. This stores the graphics device, render queue, window surface, and more.
 pub struct GraphicsContext {
     pub(crate) device: wgpu::Device,
     adapter: wgpu::Adapter,
     pub(crate) queue: wgpu::Queue,
-    pub(crate) surface: wgpu::Surface,
+    pub(crate) screen_target: ScreenTarget,
     pub(crate) preferred_format: wgpu::TextureFormat,
-    #[allow(dead_code)]
     pub(crate) surface_config: wgpu::SurfaceConfiguration,
     pub(crate) gfx_config: GraphicsConfig,
+    /// Copy of the last presented surface frame, kept while frame capture is enabled.
+    captured_frame: Option<(wgpu::Texture, mint::Vector2<u32>)>,
+    frame_capture: bool,
 
-    pub(crate) canvas_store: Vec<wgpu::TextureView>,
-    pub(crate) pipeline_store: Vec<wgpu::RenderPipeline>,
+    pub(crate) canvas_store: Store<CanvasTarget>,
+    stencil_store: Vec<(mint::Vector2<u32>, wgpu::TextureView)>,
+    pub(crate) pipeline_store: Store<ShaderPipelines>,
+    pub(crate) release_queue: ReleaseQueue,
+    /// Samplers are shared between all drawables using the same options.
+    samplers: RefCell<HashMap<SamplerOptions, Rc<wgpu::Sampler>>>,
     pub(crate) default_font: Font,
     pub(crate) glyph_brush: GlyphBrush<[Vertex; 4]>,
 
     pub(crate) texture_bind_group_layout: wgpu::BindGroupLayout,
     pub(crate) mvp_bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+
+    mipmap_shader: wgpu::ShaderModule,
+    mipmap_pipeline_layout: wgpu::PipelineLayout,
+    /// Built the first time mipmaps are generated for a texture format.
+    mipmap_pipelines: RefCell<HashMap<wgpu::TextureFormat, Rc<wgpu::RenderPipeline>>>,
 
     pub(crate) quad_mesh_buffer: Rc<MeshBuffer>,
     pub(crate) identity_instance_buffer: Rc<wgpu::Buffer>,
@@ -48,6 +76,10 @@ pub struct GraphicsContext {
     uniform_buffer_count: u64,
 
     uniform_bind_groups: Vec<wgpu::BindGroup>,
+
+    frame_instance_data: Vec<u8>,
+    frame_instance_buffer: wgpu::Buffer,
+    frame_instance_buffer_size: wgpu::BufferAddress,
 }
 
 impl GraphicsContext {
@@ -80,6 +112,57 @@ impl GraphicsContext {
 
         surface.configure(&device, &surface_config);
 
+        Self::from_parts(
+            adapter,
+            device,
+            queue,
+            ScreenTarget::Surface(surface),
+            surface_config,
+            gfx_config,
+        )
+    }
+
+    /// Creates a new graphics context without a window.
+    /// The screen is rendered into an offscreen texture instead,
+    /// use `GraphicsContext::download_screen_rgba` to read it back.
+    pub fn new_headless(
+        dimensions: impl Into<mint::Vector2<u32>>,
+        gfx_config: GraphicsConfig,
+    ) -> OblivionResult<Self> {
+        let dimensions = dimensions.into();
+        let adapter = get_headless_adapter()?;
+        let (device, queue) = get_device_queue(&adapter)?;
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: dimensions.x,
+            height: dimensions.y,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        let texture = create_offscreen_texture(&device, &surface_config);
+
+        Self::from_parts(
+            adapter,
+            device,
+            queue,
+            ScreenTarget::Offscreen(texture),
+            surface_config,
+            gfx_config,
+        )
+    }
+
+    fn from_parts(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        screen_target: ScreenTarget,
+        surface_config: wgpu::SurfaceConfiguration,
+        gfx_config: GraphicsConfig,
+    ) -> OblivionResult<Self> {
+        let preferred_format = surface_config.format;
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -117,24 +200,39 @@ impl GraphicsContext {
                 label: Some("Oblivion_MVPBindGroupLayout"),
             });
 
-        let standard_pipeline = create_pipeline(
+        let pipeline_layout =
+            create_pipeline_layout(&device, &texture_bind_group_layout, &mvp_bind_group_layout);
+
+        let standard_pipeline = create_shader_pipelines(
             "Standard",
             &device,
-            surface_config.format,
             wgpu::ShaderSource::Wgsl(include_str!("../resources/shaders/shader.wgsl").into()),
-            &texture_bind_group_layout,
-            &mvp_bind_group_layout,
+            Some("fs_premultiplied"),
         );
-        let text_pipeline = create_pipeline(
+        let text_pipeline = create_shader_pipelines(
             "Text",
             &device,
-            surface_config.format,
             wgpu::ShaderSource::Wgsl(include_str!("../resources/shaders/text_shader.wgsl").into()),
-            &texture_bind_group_layout,
-            &mvp_bind_group_layout,
+            Some("fs_premultiplied"),
         );
 
-        let pipeline_store = vec![standard_pipeline, text_pipeline];
+        // Never released, drawables refer to these as `STANDARD_PIPELINE` and `TEXT_PIPELINE`.
+        let mut pipeline_store = Store::default();
+        let standard_id = pipeline_store.insert(standard_pipeline);
+        let text_id = pipeline_store.insert(text_pipeline);
+
+        let mipmap_shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Oblivion_MipmapShader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../resources/shaders/mipmap.wgsl").into(),
+            ),
+        });
+        let mipmap_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Oblivion_MipmapPipelineLayout"),
+                bind_group_layouts: &[&texture_bind_group_layout],
+                push_constant_ranges: &[],
+            });
 
         let quad_mesh_buffer = MeshBuffer::from_slices(&device, QUAD_VERTICES, QUAD_INDICES);
 
@@ -147,18 +245,26 @@ impl GraphicsContext {
 
         let uniform_alignment = device.limits().min_uniform_buffer_offset_alignment;
 
+        let frame_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Oblivion_FrameInstanceBuffer"),
+            size: 0,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let identity_instance_buffer = Rc::new(
             device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some("Oblivion_IdentityInstanceBuffer"),
                 usage: wgpu::BufferUsages::VERTEX,
-                contents: bytemuck::cast_slice(
-                    &Transform {
+                contents: bytemuck::bytes_of(&InstanceType::new(
+                    Transform {
                         offset: [0.0, 0.0].into(),
                         ..Default::default()
                     }
-                    .as_matrix(mint::Vector2 { x: 1.0, y: 1.0 })
-                    .to_cols_array_2d(),
-                ),
+                    .as_matrix(mint::Vector2 { x: 1.0, y: 1.0 }),
+                    FULL_SRC,
+                    WHITE,
+                )),
             }),
         );
 
@@ -175,19 +281,29 @@ impl GraphicsContext {
             include_bytes!("../resources/fonts/DejaVuSans.ttf").to_vec(),
         )?;
 
-        Ok(GraphicsContext {
+        let mut ctx = GraphicsContext {
             device,
             adapter,
             queue,
-            surface,
+            screen_target,
             preferred_format,
             surface_config,
             gfx_config,
-            canvas_store: Vec::new(),
+            captured_frame: None,
+            frame_capture: false,
+            canvas_store: Store::default(),
+            stencil_store: Vec::new(),
             pipeline_store,
+            release_queue: Default::default(),
+            samplers: Default::default(),
 
             texture_bind_group_layout,
             mvp_bind_group_layout,
+            pipeline_layout,
+
+            mipmap_shader,
+            mipmap_pipeline_layout,
+            mipmap_pipelines: Default::default(),
 
             glyph_brush,
             default_font,
@@ -202,15 +318,246 @@ impl GraphicsContext {
             uniform_buffer,
             uniform_buffer_count: 0,
             uniform_bind_groups: Vec::new(),
+            frame_instance_data: Vec::new(),
+            frame_instance_buffer,
+            frame_instance_buffer_size: 0,
+        };
+        for pipeline_id in [standard_id, text_id] {
+            ctx.ensure_pipeline(pipeline_id, PipelineVariant::default());
+        }
+        Ok(ctx)
+    }
+
+    /// Builds the pipeline for this shader and variant combination if it doesn't exist yet.
+    pub(crate) fn ensure_pipeline(&mut self, pipeline_id: Handle, variant: PipelineVariant) {
+        let shader_pipelines = self
+            .pipeline_store
+            .get_mut(pipeline_id)
+            .expect("Pipeline handles are validated before use");
+        if !shader_pipelines.variants.contains_key(&variant) {
+            // Multiply only works on premultiplied color, see `BlendMode::blend_state`.
+            let fragment_entry_point = match variant.blend_mode {
+                BlendMode::Multiply => shader_pipelines
+                    .premultiplied_entry_point
+                    .unwrap_or("fs_main"),
+                _ => "fs_main",
+            };
+            let pipeline = create_pipeline(
+                &shader_pipelines.name,
+                &self.device,
+                self.surface_config.format,
+                &shader_pipelines.shader,
+                fragment_entry_point,
+                &self.pipeline_layout,
+                variant,
+            );
+            shader_pipelines.variants.insert(variant, pipeline);
+        }
+    }
+
+    /// Gets the sampler for these options, creating it if no drawable has used them yet.
+    pub(crate) fn sampler(&self, options: SamplerOptions) -> Rc<wgpu::Sampler> {
+        let mut samplers = self.samplers.borrow_mut();
+        let sampler = samplers
+            .entry(options)
+            .or_insert_with(|| Rc::new(self.device.create_sampler(&options.descriptor())));
+        Rc::clone(sampler)
+    }
+
+    /// Gets what textures of this format can be used for on this device.
+    pub(crate) fn format_features(
+        &self,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureFormatFeatures {
+        if self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            self.adapter.get_texture_format_features(format)
+        } else {
+            format.describe().guaranteed_format_features
+        }
+    }
+
+    /// Creates the bind group drawables use to sample their texture.
+    pub(crate) fn texture_bind_group(
+        &self,
+        view: &wgpu::TextureView,
+        sampler: SamplerOptions,
+        label: &str,
+    ) -> wgpu::BindGroup {
+        let sampler = self.sampler(sampler);
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some(label),
         })
     }
 
+    /// Fills every mip level after the first by downsampling the level before it.
+    /// The texture needs `RENDER_ATTACHMENT` usage and a filterable, renderable format.
+    pub(crate) fn generate_mipmaps(
+        &self,
+        texture: &wgpu::Texture,
+        format: wgpu::TextureFormat,
+        dimensions: mint::Vector2<u32>,
+        mip_level_count: u32,
+    ) {
+        let pipeline = Rc::clone(
+            self.mipmap_pipelines
+                .borrow_mut()
+                .entry(format)
+                .or_insert_with(|| {
+                    Rc::new(create_mipmap_pipeline(
+                        &self.device,
+                        format,
+                        &self.mipmap_shader,
+                        &self.mipmap_pipeline_layout,
+                    ))
+                }),
+        );
+        let views = (0..mip_level_count)
+            .map(|mip_level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Oblivion_MipmapView"),
+                    base_mip_level: mip_level,
+                    mip_level_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // The GL backend can't sample a single mip level, it would read the whole texture being rendered to.
+        // There each level is copied to a texture of its own before it's downsampled.
+        let copy_source = self.adapter.get_info().backend == wgpu::Backend::Gl;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Oblivion_MipmapCommandEncoder"),
+            });
+        for (mip_level, level) in views.windows(2).enumerate() {
+            let source_copy = copy_source.then(|| {
+                let size = wgpu::Extent3d {
+                    width: (dimensions.x >> mip_level).max(1),
+                    height: (dimensions.y >> mip_level).max(1),
+                    depth_or_array_layers: 1,
+                };
+                let source = self.device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Oblivion_MipmapSourceTexture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING,
+                });
+                encoder.copy_texture_to_texture(
+                    wgpu::ImageCopyTexture {
+                        mip_level: mip_level as u32,
+                        ..texture.as_image_copy()
+                    },
+                    source.as_image_copy(),
+                    size,
+                );
+                source.create_view(&wgpu::TextureViewDescriptor::default())
+            });
+            let bind_group = self.texture_bind_group(
+                source_copy.as_ref().unwrap_or(&level[0]),
+                SamplerOptions::linear(),
+                "Oblivion_MipmapBindGroup",
+            );
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Oblivion_MipmapRenderPass"),
+                color_attachments: &[wgpu::RenderPassColorAttachment {
+                    view: &level[1],
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    /// Frees the store slots of canvases and shaders whose last clone has been dropped.
+    pub(crate) fn release_resources(&mut self) {
+        for handle in self.release_queue.borrow_mut().drain(..) {
+            match handle {
+                StoreHandle::Canvas(handle) => {
+                    self.canvas_store.remove(handle);
+                }
+                StoreHandle::Pipeline(handle) => {
+                    self.pipeline_store.remove(handle);
+                }
+            }
+        }
+    }
+
+    /// Makes sure every canvas and shader used by the render is still alive.
+    fn validate_render(&self, render: &Render) -> OblivionResult<()> {
+        for group in &render.render_groups {
+            if let TargetId::CanvasId(canvas_id) = group.target_id {
+                if !self.canvas_store.contains(canvas_id) {
+                    return Err(OblivionError::StaleHandle);
+                }
+            }
+            if group
+                .queue
+                .iter()
+                .any(|data| !self.pipeline_store.contains(data.instance_data.pipeline_id))
+            {
+                return Err(OblivionError::StaleHandle);
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_projection(&mut self, dimensions: impl Into<mint::Vector2<f32>>) {
         let dimensions: mint::Vector2<f32> = dimensions.into();
         self.projection = projection_matrix(dimensions.x, dimensions.y);
         self.gfx_config.render_dimensions = dimensions;
     }
 
+    /// Resizes the surface, this should be called whenever the window is resized.
+    pub fn resize(&mut self, new_dimensions: impl Into<mint::Vector2<u32>>) {
+        let new_dimensions = new_dimensions.into();
+        // Surfaces can't be configured with a size of zero, which is what minimized windows report.
+        if new_dimensions.x == 0 || new_dimensions.y == 0 {
+            return;
+        }
+        self.surface_config.width = new_dimensions.x;
+        self.surface_config.height = new_dimensions.y;
+        self.configure_surface();
+        // Stencil buffers are created lazily, so there's no need to keep ones that might not fit anymore.
+        self.stencil_store.clear();
+    }
+
+    fn configure_surface(&mut self) {
+        match &mut self.screen_target {
+            ScreenTarget::Surface(surface) => surface.configure(&self.device, &self.surface_config),
+            ScreenTarget::Offscreen(texture) => {
+                *texture = create_offscreen_texture(&self.device, &self.surface_config)
+            }
+        }
+    }
+
     pub fn surface_dimensions(&self) -> mint::Vector2<u32> {
         mint::Vector2 {
             x: self.surface_config.width,
@@ -234,17 +581,92 @@ impl GraphicsContext {
         }
     }
 
+    fn target_dimensions(&self, target_id: TargetId) -> mint::Vector2<u32> {
+        match target_id {
+            TargetId::Screen => self.surface_dimensions(),
+            TargetId::CanvasId(canvas_id) => self.canvas_store[canvas_id].dimensions,
+        }
+    }
+
+    /// Render dimensions used by a render group.
+    fn render_dimensions(&self, group: &RenderGroup) -> mint::Vector2<f32> {
+        group
+            .projection
+            .unwrap_or(self.gfx_config.render_dimensions)
+    }
+
+    /// Creates a stencil buffer for render targets of this size if there isn't one already.
+    fn ensure_stencil_view(&mut self, dimensions: mint::Vector2<u32>) {
+        if self
+            .stencil_store
+            .iter()
+            .any(|(stencil_dimensions, _)| *stencil_dimensions == dimensions)
+        {
+            return;
+        }
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            size: wgpu::Extent3d {
+                width: dimensions.x,
+                height: dimensions.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: STENCIL_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            label: Some("Oblivion_StencilTexture"),
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.stencil_store.push((dimensions, view));
+    }
+
+    /// Maps a rectangle in render units to a `[x, y, width, height]` pixel rectangle clamped to the target.
+    fn scissor_pixels(
+        rect: &Rect,
+        render_dim: mint::Vector2<f32>,
+        target_dimensions: mint::Vector2<u32>,
+    ) -> [u32; 4] {
+        let scale = mint::Vector2 {
+            x: target_dimensions.x as f32 / render_dim.x,
+            y: target_dimensions.y as f32 / render_dim.y,
+        };
+        let to_pixels = |value: f32, scale: f32, max: u32| {
+            (value * scale).round().clamp(0.0, max as f32) as u32
+        };
+        let left = to_pixels(rect.position.x, scale.x, target_dimensions.x);
+        let top = to_pixels(rect.position.y, scale.y, target_dimensions.y);
+        let right = to_pixels(rect.position.x + rect.size.x, scale.x, target_dimensions.x);
+        let bottom = to_pixels(rect.position.y + rect.size.y, scale.y, target_dimensions.y);
+        [
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        ]
+    }
+
     fn render_group(
-        &mut self,
+        &self,
         encoder: &mut wgpu::CommandEncoder,
         output_view: &wgpu::TextureView,
         group: &RenderGroup,
+        batches: &[DrawBatch],
         uniform_start_idx: usize,
     ) {
-        let uniform_alignment = self.uniform_alignment as wgpu::BufferAddress;
+        let target_dimensions = self.target_dimensions(group.target_id);
+        let render_dimensions = self.render_dimensions(group);
         let view = match group.target_id {
             TargetId::Screen => output_view,
-            TargetId::CanvasId(canvas_id) => &self.canvas_store[canvas_id],
+            TargetId::CanvasId(canvas_id) => &self.canvas_store[canvas_id].view,
+        };
+        let stencil_view = if group.uses_stencil {
+            self.stencil_store
+                .iter()
+                .find(|(dimensions, _)| *dimensions == target_dimensions)
+                .map(|(_, view)| view)
+        } else {
+            None
         };
 
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -265,90 +687,220 @@ impl GraphicsContext {
                     store: true,
                 },
             }],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: stencil_view.map(|view| {
+                wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: None,
+                    stencil_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(0),
+                        store: false,
+                    }),
+                }
+            }),
         });
 
         if !group.queue.is_empty() {
-            for (
-                idx,
-                RenderData {
+            let mut current_scissor_rect = [0, 0, target_dimensions.x, target_dimensions.y];
+
+            for (batch_idx, batch) in batches.iter().enumerate() {
+                let RenderData {
+                    pipeline_data,
+                    instance_count,
                     instance_data:
                         DrawData {
-                            transform,
-                            uniform_extra,
+                            pipeline_id,
+                            variant,
+                            stencil_reference,
+                            scissor,
                             ..
                         },
-                    pipeline_data:
-                        PipelineData {
-                            object_dimensions, ..
-                        },
-                    ..
-                },
-            ) in group.queue.iter().enumerate()
-            {
-                let start = (idx + uniform_start_idx) * uniform_alignment as usize;
-                let mat = self.projection * transform.as_matrix(*object_dimensions);
-                self.uniform_buffer_data[start..start + UNIFORM_SIZE]
-                    .copy_from_slice(bytemuck::cast_slice(&mat.to_cols_array_2d()));
-                let extra_start = start + UNIFORM_SIZE;
-                self.uniform_buffer_data[extra_start..extra_start + uniform_extra.len()]
-                    .copy_from_slice(uniform_extra);
-            }
-            self.queue
-                .write_buffer(&self.uniform_buffer, 0, &self.uniform_buffer_data);
-
-            for (
-                idx,
-                RenderData {
-                    pipeline_data,
-                    instance_count,
-                    instance_data: DrawData { pipeline_id, .. },
-                },
-            ) in group.queue.iter().enumerate()
-            {
+                } = &group.queue[batch.draws[0]];
+                let scissor_rect = match scissor {
+                    Some(scissor) => {
+                        Self::scissor_pixels(scissor, render_dimensions, target_dimensions)
+                    }
+                    None => [0, 0, target_dimensions.x, target_dimensions.y],
+                };
+                if scissor_rect[2] == 0 || scissor_rect[3] == 0 {
+                    continue;
+                }
+                if current_scissor_rect != scissor_rect {
+                    let [x, y, width, height] = scissor_rect;
+                    render_pass.set_scissor_rect(x, y, width, height);
+                    current_scissor_rect = scissor_rect;
+                }
                 //println!("Drawing pipeline {}", *pipeline_id);
-                render_pass.set_pipeline(&self.pipeline_store[*pipeline_id]);
+                render_pass.set_pipeline(&self.pipeline_store[*pipeline_id].variants[variant]);
+                if group.uses_stencil {
+                    render_pass.set_stencil_reference(*stencil_reference);
+                }
                 render_pass.set_bind_group(0, &pipeline_data.bind_group, &[]);
                 render_pass.set_bind_group(
                     1,
-                    &self.uniform_bind_groups[uniform_start_idx + idx],
+                    &self.uniform_bind_groups[uniform_start_idx + batch_idx],
                     &[],
                 );
                 render_pass.set_vertex_buffer(0, pipeline_data.mesh_buffer.vertex.0.slice(..));
-                render_pass.set_vertex_buffer(1, pipeline_data.instance_buffer.slice(..));
+                let instances = if batch.uses_frame_instances {
+                    let start = batch.instance_offset * INSTANCE_SIZE as wgpu::BufferAddress;
+                    render_pass.set_vertex_buffer(1, self.frame_instance_buffer.slice(start..));
+                    0..batch.draws.len() as u32
+                } else {
+                    render_pass.set_vertex_buffer(1, pipeline_data.instance_buffer.slice(..));
+                    0..*instance_count
+                };
                 render_pass.set_index_buffer(
                     pipeline_data.mesh_buffer.index.0.slice(..),
                     wgpu::IndexFormat::Uint16,
                 );
-                render_pass.draw_indexed(
-                    0..pipeline_data.mesh_buffer.index.1,
-                    0,
-                    0..*instance_count,
-                );
+                render_pass.draw_indexed(0..pipeline_data.mesh_buffer.index.1, 0, instances);
+            }
+        }
+    }
+
+    /// Writes the uniforms of every batch, one aligned slot each, in the order the groups are rendered.
+    fn write_uniforms(&mut self, render: &Render, group_batches: &[Vec<DrawBatch>]) {
+        let uniform_alignment = self.uniform_alignment as usize;
+        let mut start = 0;
+        for (group, batches) in render.render_groups.iter().zip(group_batches) {
+            let target_dimensions = self.target_dimensions(group.target_id);
+            let render_dimensions = self.render_dimensions(group);
+            let projection = match group.projection {
+                Some(dimensions) => projection_matrix(dimensions.x, dimensions.y),
+                None => self.projection,
+            };
+            for batch in batches {
+                let data = &group.queue[batch.draws[0]];
+                let DrawData {
+                    pipeline_id,
+                    camera,
+                    transform,
+                    uniform_extra,
+                    ..
+                } = &data.instance_data;
+                let view_projection = match camera {
+                    Some(camera) => projection * camera.view_matrix(render_dimensions),
+                    None => projection,
+                };
+                // These draws have their transforms in the frame instance buffer instead.
+                let mat = if batch.uses_frame_instances {
+                    view_projection
+                } else {
+                    view_projection * data.model_matrix(render_dimensions, target_dimensions)
+                };
+                self.uniform_buffer_data[start..start + UNIFORM_SIZE]
+                    .copy_from_slice(bytemuck::cast_slice(&mat.to_cols_array_2d()));
+                let extra_start = start + UNIFORM_SIZE;
+                // Frame instances already include the color.
+                let color = if batch.uses_frame_instances {
+                    WHITE
+                } else {
+                    transform.color
+                };
+                let tint = [color.r, color.g, color.b, color.a];
+                // The built-in shaders tint draws with their own instances by this color,
+                // custom shaders get their shader data here instead.
+                let extra: &[u8] =
+                    if *pipeline_id == STANDARD_PIPELINE || *pipeline_id == TEXT_PIPELINE {
+                        bytemuck::cast_slice(&tint)
+                    } else {
+                        uniform_extra
+                    };
+                self.uniform_buffer_data[extra_start..extra_start + extra.len()]
+                    .copy_from_slice(extra);
+                start += uniform_alignment;
             }
         }
+        if start > 0 {
+            self.queue
+                .write_buffer(&self.uniform_buffer, 0, &self.uniform_buffer_data[..start]);
+        }
+    }
+
+    /// Writes the transforms of merged draws into the frame instance buffer.
+    fn write_frame_instances(&mut self, render: &Render, group_batches: &mut [Vec<DrawBatch>]) {
+        self.frame_instance_data.clear();
+        for (group, batches) in render.render_groups.iter().zip(group_batches.iter_mut()) {
+            let render_dimensions = self.render_dimensions(group);
+            let target_dimensions = self.target_dimensions(group.target_id);
+            for batch in batches
+                .iter_mut()
+                .filter(|batch| batch.uses_frame_instances)
+            {
+                batch.instance_offset = (self.frame_instance_data.len() / INSTANCE_SIZE) as u64;
+                for &idx in &batch.draws {
+                    let data = &group.queue[idx];
+                    let mat = data.model_matrix(render_dimensions, target_dimensions);
+                    let instance = InstanceType::new(
+                        mat,
+                        data.instance_data.src,
+                        data.instance_data.transform.color,
+                    );
+                    self.frame_instance_data
+                        .extend_from_slice(bytemuck::bytes_of(&instance));
+                }
+            }
+        }
+        if self.frame_instance_data.is_empty() {
+            return;
+        }
+
+        let size = self.frame_instance_data.len() as wgpu::BufferAddress;
+        if size > self.frame_instance_buffer_size {
+            let new_size = size * 2;
+            self.frame_instance_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Oblivion_FrameInstanceBuffer"),
+                size: new_size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            self.frame_instance_buffer_size = new_size;
+        }
+        self.queue
+            .write_buffer(&self.frame_instance_buffer, 0, &self.frame_instance_data);
     }
 
     // Maybe take render as &mut?
     /// Submits the render object to Oblivion's rendering system.
     pub fn submit_render(&mut self, render: &Render) -> OblivionResult<()> {
         //println!("Starting render!");
+        self.release_resources();
+        self.validate_render(render)?;
         let uniform_alignment = self.uniform_alignment as wgpu::BufferAddress;
-        let output = self
-            .surface
-            .get_current_texture()
-            .map_err(OblivionError::RetrieveFrameError)?;
-        let view = output
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-
-        let total_queue_len: u64 = render
+        let (output, view) = match &self.screen_target {
+            ScreenTarget::Surface(surface) => {
+                let output = match surface.get_current_texture() {
+                    Ok(output) => Ok(output),
+                    // The surface needs to be reconfigured, after that it's worth trying again.
+                    Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                        surface.configure(&self.device, &self.surface_config);
+                        surface.get_current_texture()
+                    }
+                    Err(e) => Err(e),
+                }
+                .map_err(OblivionError::RetrieveFrameError)?;
+                let view = output
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            ScreenTarget::Offscreen(texture) => (
+                None,
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
+
+        let mut group_batches = render
             .render_groups
             .iter()
-            .map(|group| group.queue.len() as u64)
+            .map(|group| group.batches(&self.identity_instance_buffer))
+            .collect::<Vec<_>>();
+        let total_batch_count: u64 = group_batches
+            .iter()
+            .map(|batches| batches.len() as u64)
             .sum();
-        if total_queue_len > self.uniform_buffer_count {
-            let new_uniform_buffer_count = total_queue_len * 2;
+        if total_batch_count > self.uniform_buffer_count {
+            let new_uniform_buffer_count = total_batch_count * 2;
             self.uniform_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("Oblivion_UniformBuffer"),
                 size: new_uniform_buffer_count * uniform_alignment,
@@ -406,23 +958,140 @@ impl GraphicsContext {
             });
         }
 
+        // Pipelines can't be created while the render passes are borrowing the pipeline store.
+        for data in render
+            .render_groups
+            .iter()
+            .flat_map(|group| group.queue.iter())
+        {
+            self.ensure_pipeline(data.instance_data.pipeline_id, data.instance_data.variant);
+        }
+        let stencil_dimensions = render
+            .render_groups
+            .iter()
+            .filter(|group| group.uses_stencil)
+            .map(|group| self.target_dimensions(group.target_id))
+            .collect::<Vec<_>>();
+        // Only keep the stencil buffers this render masks, so old canvas and window sizes are freed.
+        self.stencil_store
+            .retain(|(dimensions, _)| stencil_dimensions.contains(dimensions));
+        for dimensions in stencil_dimensions {
+            self.ensure_stencil_view(dimensions);
+        }
+
+        self.write_frame_instances(render, &mut group_batches);
+        self.write_uniforms(render, &group_batches);
+
         let mut uniform_start_idx = 0;
-        for group in &render.render_groups {
+        for (group, batches) in render.render_groups.iter().zip(&group_batches) {
             /*println!(
                 "Group Target: {:?} ({} draws)",
                 group.target_id,
                 group.queue.len()
             );*/
-            self.render_group(&mut encoder, &view, group, uniform_start_idx);
-            uniform_start_idx += group.queue.len();
+            self.render_group(&mut encoder, &view, group, batches, uniform_start_idx);
+            uniform_start_idx += batches.len();
+        }
+
+        if let Some(output) = &output {
+            if self.frame_capture {
+                self.copy_captured_frame(&mut encoder, &output.texture);
+            }
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(output) = output {
+            output.present();
+        }
         //println!("Render finished!");
         Ok(())
     }
 
+    /// Gets the raw RGBA data of the last rendered frame.
+    /// Only available for contexts created with `GraphicsContext::new_headless`.
+    pub fn download_screen_rgba(&self) -> OblivionResult<Vec<u8>> {
+        match &self.screen_target {
+            ScreenTarget::Offscreen(texture) => download_texture(
+                "Screen",
+                &self.device,
+                &self.queue,
+                texture,
+                self.surface_dimensions(),
+                4,
+            ),
+            ScreenTarget::Surface(_) => Err(OblivionError::NotHeadless),
+        }
+    }
+
+    /// Makes `submit_render` keep a copy of each frame presented to the window, so it can be read back by
+    /// `capture_frame`. This adds `COPY_SRC` to the surface usage. Headless contexts can always be captured.
+    pub fn set_frame_capture(&mut self, enabled: bool) {
+        self.frame_capture = enabled;
+        if let ScreenTarget::Surface(surface) = &self.screen_target {
+            self.surface_config
+                .usage
+                .set(wgpu::TextureUsages::COPY_SRC, enabled);
+            surface.configure(&self.device, &self.surface_config);
+        }
+        if !enabled {
+            self.captured_frame = None;
+        }
+    }
+
+    fn copy_captured_frame(&mut self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::Texture) {
+        let dimensions = self.surface_dimensions();
+        if !matches!(&self.captured_frame, Some((_, captured)) if *captured == dimensions) {
+            let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width: dimensions.x,
+                    height: dimensions.y,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.surface_config.format,
+                usage: wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+                label: Some("Oblivion_CapturedFrameTexture"),
+            });
+            self.captured_frame = Some((texture, dimensions));
+        }
+        let (texture, _) = self.captured_frame.as_ref().unwrap();
+        encoder.copy_texture_to_texture(
+            frame.as_image_copy(),
+            texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: dimensions.x,
+                height: dimensions.y,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Reads back the last frame rendered by `submit_render`, for example to take a screenshot.
+    /// Frames presented to a window are only kept after enabling `set_frame_capture`.
+    #[cfg(feature = "image")]
+    pub fn capture_frame(&self) -> OblivionResult<image::RgbaImage> {
+        let (texture, dimensions) = match (&self.screen_target, &self.captured_frame) {
+            (ScreenTarget::Offscreen(texture), _) => (texture, self.surface_dimensions()),
+            (ScreenTarget::Surface(_), Some((texture, dimensions))) => (texture, *dimensions),
+            (ScreenTarget::Surface(_), None) => return Err(OblivionError::NoCapturedFrame),
+        };
+        let data = download_texture(
+            "CapturedFrame",
+            &self.device,
+            &self.queue,
+            texture,
+            dimensions,
+            4,
+        )?;
+        Ok(crate::helpers::rgba_image(
+            self.surface_config.format,
+            dimensions,
+            data,
+        ))
+    }
+
     pub fn renderer_info(&self) -> String {
         format!(
             "Using '{}' ({:?}) on {:?}",
@@ -433,6 +1102,25 @@ impl GraphicsContext {
     }
 }
 
+fn create_offscreen_texture(
+    device: &wgpu::Device,
+    surface_config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size: wgpu::Extent3d {
+            width: surface_config.width,
+            height: surface_config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: surface_config.format,
+        usage: surface_config.usage,
+        label: Some("Oblivion_OffscreenTexture"),
+    })
+}
+
 fn projection_matrix(width: f32, height: f32) -> glam::Mat4 {
     // ?? Stolen from ggez
     fn ortho(left: f32, right: f32, top: f32, bottom: f32, far: f32, near: f32) -> [[f32; 4]; 4] {
@@ -466,3 +1154,63 @@ fn projection_matrix(width: f32, height: f32) -> glam::Mat4 {
     }
     glam::Mat4::from_cols_array_2d(&ortho(0.0, width, 0.0, height, -1.0, 1.0))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Canvas, DrawMode, MeshBuilder, Transform};
+
+    #[test]
+    fn stencil_buffers_follow_masked_targets() {
+        let mut ctx = GraphicsContext::new_headless(
+            [320, 180],
+            GraphicsConfig {
+                vsync: false,
+                render_dimensions: [1.0, 1.0].into(),
+            },
+        )
+        .unwrap_or_else(|e| panic!("Creating a headless context failed: {}", e));
+        let quad = MeshBuilder::new()
+            .quad(
+                [0.0, 0.0],
+                [0.5, 1.0],
+                [1.0, 1.0, 1.0, 1.0],
+                DrawMode::fill(),
+            )
+            .unwrap()
+            .build(&ctx);
+        let canvas = Canvas::new(&mut ctx, [64, 32]);
+        let masked = |canvas: Option<&Canvas>| {
+            let mut render = Render::new();
+            if let Some(canvas) = canvas {
+                crate::push_canvas(&mut render, canvas);
+            }
+            crate::push_mask(&mut render, &quad, Transform::default());
+            quad.draw(&mut render, Transform::default());
+            crate::pop_mask(&mut render);
+            if canvas.is_some() {
+                crate::pop_canvas(&mut render);
+            }
+            render
+        };
+        let stencil_dimensions = |ctx: &GraphicsContext| {
+            ctx.stencil_store
+                .iter()
+                .map(|(dim, _)| *dim)
+                .collect::<Vec<_>>()
+        };
+
+        ctx.submit_render(&masked(None)).unwrap();
+        assert_eq!(stencil_dimensions(&ctx), [[320, 180].into()]);
+        ctx.submit_render(&masked(Some(&canvas))).unwrap();
+        assert_eq!(stencil_dimensions(&ctx), [[64, 32].into()]);
+        ctx.submit_render(&Render::new()).unwrap();
+        assert!(ctx.stencil_store.is_empty());
+
+        ctx.submit_render(&masked(None)).unwrap();
+        ctx.resize([200, 100]);
+        assert!(ctx.stencil_store.is_empty());
+        ctx.submit_render(&masked(None)).unwrap();
+        assert_eq!(stencil_dimensions(&ctx), [[200, 100].into()]);
+    }
+}
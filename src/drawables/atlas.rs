@@ -0,0 +1,287 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::{
+    GraphicsContext, Image, OblivionError, OblivionResult, PixelFormat, Rect, Render, SizeMode,
+    Transform, STANDARD_PIPELINE,
+};
+
+struct AtlasEntry<K> {
+    key: K,
+    dimensions: mint::Vector2<u32>,
+    data: Vec<u8>,
+}
+
+/// Packs many RGBA images into as few textures as possible so they can share a bind group.
+///
+/// Example usage:
+/// ```rust
+/// let mut builder = TextureAtlasBuilder::new();
+/// builder.padding(2).extrude(true);
+/// builder.add("player", [16, 16], &player_rgba)?;
+/// builder.add("enemy", [16, 24], &enemy_rgba)?;
+/// let atlas = builder.build(ctx)?;
+/// /* ... */
+/// atlas.get(&"player").unwrap().draw(&mut render, Transform::default());
+/// ```
+pub struct TextureAtlasBuilder<K> {
+    entries: Vec<AtlasEntry<K>>,
+    max_size: u32,
+    padding: u32,
+    extrude: bool,
+}
+
+impl<K: Eq + Hash> TextureAtlasBuilder<K> {
+    /// Creates a new atlas builder with 2048x2048 pages and 1 pixel of padding.
+    pub fn new() -> Self {
+        TextureAtlasBuilder {
+            entries: Vec::new(),
+            max_size: 2048,
+            padding: 1,
+            extrude: false,
+        }
+    }
+
+    /// Sets the maximum width and height of each atlas texture. Larger images get a texture of their own.
+    pub fn max_size(&mut self, max_size: u32) -> &mut TextureAtlasBuilder<K> {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the number of pixels kept free around each image.
+    pub fn padding(&mut self, padding: u32) -> &mut TextureAtlasBuilder<K> {
+        self.padding = padding;
+        self
+    }
+
+    /// Fills the padding with the edge pixels of each image, which prevents neighbours bleeding in when filtering.
+    pub fn extrude(&mut self, extrude: bool) -> &mut TextureAtlasBuilder<K> {
+        self.extrude = extrude;
+        self
+    }
+
+    /// Adds an RGBA image to the atlas. Adding the same key twice replaces the previous image.
+    pub fn add(
+        &mut self,
+        key: K,
+        dimensions: impl Into<mint::Vector2<u32>>,
+        data: &[u8],
+    ) -> OblivionResult<&mut TextureAtlasBuilder<K>> {
+        let dimensions = dimensions.into();
+        if dimensions.x == 0
+            || dimensions.y == 0
+            || data.len() as u64 != dimensions.x as u64 * dimensions.y as u64 * 4
+        {
+            return Err(OblivionError::InvalidImageData {
+                dimensions,
+                format: PixelFormat::Rgba8UnormSrgb,
+                len: data.len(),
+            });
+        }
+        self.entries.retain(|entry| entry.key != key);
+        self.entries.push(AtlasEntry {
+            key,
+            dimensions,
+            data: data.to_vec(),
+        });
+        Ok(self)
+    }
+
+    /// Packs the images and uploads the atlas textures.
+    pub fn build(self, ctx: &GraphicsContext) -> OblivionResult<TextureAtlas<K>> {
+        let padding = self.padding;
+        let padded = |entry: &AtlasEntry<K>| {
+            (
+                entry.dimensions.x + padding * 2,
+                entry.dimensions.y + padding * 2,
+            )
+        };
+
+        // Shelf packing, tallest images first so shelves waste as little space as possible.
+        let mut order = (0..self.entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&idx| std::cmp::Reverse(self.entries[idx].dimensions.y));
+
+        let mut pages: Vec<PageLayout> = Vec::new();
+        // Only the most recent page is packed into, oversized images don't close it.
+        let mut open_page = None;
+        // Page, x and y of the padded area of each entry.
+        let mut placements = vec![(0, 0, 0); self.entries.len()];
+        for idx in order {
+            let (width, height) = padded(&self.entries[idx]);
+            if width > self.max_size || height > self.max_size {
+                pages.push(PageLayout {
+                    width,
+                    height,
+                    ..Default::default()
+                });
+                placements[idx] = (pages.len() - 1, 0, 0);
+                continue;
+            }
+            let placement = open_page.and_then(|page_idx: usize| {
+                pages[page_idx]
+                    .place(width, height, self.max_size)
+                    .map(|(x, y)| (page_idx, x, y))
+            });
+            placements[idx] = match placement {
+                Some(placement) => placement,
+                None => {
+                    let mut page = PageLayout::default();
+                    let (x, y) = page
+                        .place(width, height, self.max_size)
+                        .expect("Image fits in an empty page");
+                    pages.push(page);
+                    open_page = Some(pages.len() - 1);
+                    (pages.len() - 1, x, y)
+                }
+            };
+        }
+
+        let mut page_data = pages
+            .iter()
+            .map(|page| vec![0; (page.width * page.height * 4) as usize])
+            .collect::<Vec<_>>();
+        for (entry, &(page_idx, x, y)) in self.entries.iter().zip(&placements) {
+            let page_width = pages[page_idx].width;
+            let data = &mut page_data[page_idx];
+            let border = if self.extrude { padding as i64 } else { 0 };
+            let (width, height) = (entry.dimensions.x as i64, entry.dimensions.y as i64);
+            for row in -border..height + border {
+                let src_row = row.clamp(0, height - 1);
+                for column in -border..width + border {
+                    let src_column = column.clamp(0, width - 1);
+                    let src = ((src_row * width + src_column) * 4) as usize;
+                    let dst_x = (x + padding) as i64 + column;
+                    let dst_y = (y + padding) as i64 + row;
+                    let dst = ((dst_y * page_width as i64 + dst_x) * 4) as usize;
+                    data[dst..dst + 4].copy_from_slice(&entry.data[src..src + 4]);
+                }
+            }
+        }
+
+        let pages = pages
+            .iter()
+            .zip(&page_data)
+            .map(|(page, data)| Image::new(ctx, [page.width, page.height], data))
+            .collect::<OblivionResult<Vec<_>>>()?;
+        let regions = self
+            .entries
+            .into_iter()
+            .zip(placements)
+            .map(|(entry, (page_idx, x, y))| {
+                let page = &pages[page_idx];
+                let region = AtlasRegion {
+                    image: page.clone(),
+                    page: page_idx,
+                    src: Rect::new(
+                        (x + padding) as f32 / page.tex_dim.x,
+                        (y + padding) as f32 / page.tex_dim.y,
+                        entry.dimensions.x as f32 / page.tex_dim.x,
+                        entry.dimensions.y as f32 / page.tex_dim.y,
+                    ),
+                    dimensions: entry.dimensions,
+                };
+                (entry.key, region)
+            })
+            .collect();
+        Ok(TextureAtlas { pages, regions })
+    }
+}
+
+impl<K: Eq + Hash> Default for TextureAtlasBuilder<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Free space tracking of an atlas texture while packing.
+#[derive(Default)]
+struct PageLayout {
+    width: u32,
+    height: u32,
+    cursor_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+}
+
+impl PageLayout {
+    /// Finds a spot for the area, starting a new shelf if the current one is full.
+    fn place(&mut self, width: u32, height: u32, max_size: u32) -> Option<(u32, u32)> {
+        if self.cursor_x.saturating_add(width) > max_size {
+            self.shelf_y = self.shelf_y.saturating_add(self.shelf_height);
+            self.cursor_x = 0;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y.saturating_add(height) > max_size {
+            return None;
+        }
+        let position = (self.cursor_x, self.shelf_y);
+        self.cursor_x += width;
+        self.shelf_height = self.shelf_height.max(height);
+        self.width = self.width.max(self.cursor_x);
+        self.height = self.height.max(self.shelf_y + self.shelf_height);
+        Some(position)
+    }
+}
+
+/// Textures built by `TextureAtlasBuilder` and the regions of the images packed into them.
+pub struct TextureAtlas<K> {
+    pages: Vec<Image>,
+    regions: HashMap<K, AtlasRegion>,
+}
+
+impl<K: Eq + Hash> TextureAtlas<K> {
+    /// Gets the region of an image added to the builder.
+    pub fn get(&self, key: &K) -> Option<&AtlasRegion> {
+        self.regions.get(key)
+    }
+
+    /// Gets the atlas textures.
+    pub fn pages(&self) -> &[Image] {
+        &self.pages
+    }
+
+    /// Iterates over all regions in no particular order.
+    pub fn regions(&self) -> impl Iterator<Item = (&K, &AtlasRegion)> {
+        self.regions.iter()
+    }
+}
+
+/// An image packed into a texture atlas, drawn like an `Image` of its own.
+#[derive(Clone)]
+pub struct AtlasRegion {
+    image: Image,
+    page: usize,
+    src: Rect,
+    dimensions: mint::Vector2<u32>,
+}
+
+impl AtlasRegion {
+    /// Index of the atlas texture this region is on.
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Area of the atlas texture covered by this region, in UV coordinates.
+    pub fn src(&self) -> Rect {
+        self.src
+    }
+
+    /// Dimensions of the original image in pixels.
+    pub fn dimensions(&self) -> mint::Vector2<u32> {
+        self.dimensions
+    }
+
+    /// Sets how big the region is drawn before the transform is applied, this defaults to `SizeMode::Render`.
+    pub fn set_size_mode(&mut self, size_mode: SizeMode) {
+        self.image.set_size_mode(size_mode);
+    }
+
+    /// Pushes this region to the draw queue.
+    pub fn draw(&self, render: &mut Render, transform: Transform) {
+        let draw = render.push_data(self.image.data.clone(), 1, transform, STANDARD_PIPELINE);
+        draw.src = self.src;
+        draw.size = self.image.size_mode.object_size(mint::Vector2 {
+            x: self.dimensions.x as f32,
+            y: self.dimensions.y as f32,
+        });
+    }
+}
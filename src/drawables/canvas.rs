@@ -1,21 +1,38 @@
-use std::{num::NonZeroU32, rc::Rc};
+use std::rc::Rc;
 
-use crate::{GraphicsContext, OblivionError, OblivionResult, PipelineData, Render, Transform};
+use crate::{
+    helpers::download_texture, CanvasTarget, GraphicsContext, Handle, OblivionResult, PipelineData,
+    Render, SamplerOptions, SizeMode, StoreGuard, StoreHandle, Transform, STANDARD_PIPELINE,
+};
 
 // TODO make this a wrapper of image maybe?
 
 /// Canvases are used as rendering target to create a fake screen.
+///
+/// The canvas is removed from the context once the last clone is dropped.
 #[derive(Clone)]
 pub struct Canvas {
-    pub(crate) canvas_id: usize,
+    pub(crate) canvas_id: Handle,
+    _guard: Rc<StoreGuard>,
     pub(crate) texture: Rc<wgpu::Texture>,
     pub(crate) data: PipelineData,
+    size_mode: SizeMode,
+    sampler: SamplerOptions,
     pub dimensions: mint::Vector2<u32>,
 }
 
 impl Canvas {
     /// Creates a new canvas.
     pub fn new(ctx: &mut GraphicsContext, dimensions: impl Into<mint::Vector2<u32>>) -> Self {
+        Self::with_sampler(ctx, dimensions, SamplerOptions::default())
+    }
+
+    /// Creates a new canvas that is sampled with custom options when drawn.
+    pub fn with_sampler(
+        ctx: &mut GraphicsContext,
+        dimensions: impl Into<mint::Vector2<u32>>,
+        sampler: SamplerOptions,
+    ) -> Self {
         let dimensions = dimensions.into();
         let size = wgpu::Extent3d {
             width: dimensions.x,
@@ -35,35 +52,20 @@ impl Canvas {
         });
 
         let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        let bind_group = ctx.texture_bind_group(
+            &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            sampler,
+            "Oblivion_CanvasBindGroup",
+        );
 
-        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &ctx.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("Oblivion_CanvasBindGroup"),
+        ctx.release_resources();
+        let canvas_id = ctx.canvas_store.insert(CanvasTarget {
+            view: texture_view,
+            dimensions,
         });
-
-        ctx.canvas_store.push(texture_view);
-        let id = ctx.canvas_store.len() - 1;
         Canvas {
-            canvas_id: id,
+            canvas_id,
+            _guard: StoreGuard::new(StoreHandle::Canvas(canvas_id), &ctx.release_queue),
             data: PipelineData {
                 mesh_buffer: Rc::clone(&ctx.quad_mesh_buffer),
                 bind_group: Rc::new(bind_group),
@@ -71,66 +73,64 @@ impl Canvas {
                 object_dimensions: mint::Vector2 { x: 1.0, y: 1.0 },
             },
             texture: Rc::new(texture),
+            size_mode: SizeMode::Unit,
+            sampler,
             dimensions,
         }
     }
 
     /// Gets the raw RGBA data of this canvas's underlying texture.
     pub fn download_rgba(&self, ctx: &mut GraphicsContext) -> OblivionResult<Vec<u8>> {
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
-        let byte_width = self.dimensions.x as u64 * 4;
-        let padded_width_padding = (align - byte_width % align) % align;
-        let padded_width = byte_width + padded_width_padding;
+        download_texture(
+            "Canvas",
+            &ctx.device,
+            &ctx.queue,
+            &self.texture,
+            self.dimensions,
+            4,
+        )
+    }
 
-        let download_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Oblivion_CanvasUploadBuffer"),
-            size: padded_width * self.dimensions.y as u64,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
-        let mut command_encoder =
-            ctx.device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Oblivion_CanvasCommandEncoder"),
-                });
-        command_encoder.copy_texture_to_buffer(
-            wgpu::ImageCopyTexture {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d { x: 0, y: 0, z: 0 },
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyBuffer {
-                buffer: &download_buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: NonZeroU32::new(padded_width as u32),
-                    rows_per_image: NonZeroU32::new(self.dimensions.y as u32),
-                },
-            },
-            wgpu::Extent3d {
-                width: self.dimensions.x as u32,
-                height: self.dimensions.y as u32,
-                depth_or_array_layers: 1,
-            },
-        );
-        ctx.queue.submit(std::iter::once(command_encoder.finish()));
+    /// Saves the contents of this canvas as a PNG file.
+    #[cfg(feature = "image")]
+    pub fn save_png(
+        &self,
+        ctx: &mut GraphicsContext,
+        path: impl AsRef<std::path::Path>,
+    ) -> OblivionResult<()> {
+        let data = self.download_rgba(ctx)?;
+        crate::helpers::rgba_image(ctx.preferred_format, self.dimensions, data)
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(crate::OblivionError::SaveImage)
+    }
 
-        let fut = download_buffer.slice(..).map_async(wgpu::MapMode::Read);
-        ctx.device.poll(wgpu::Maintain::Wait);
-        pollster::block_on(fut).map_err(OblivionError::MapBuffer)?;
+    /// Gets the options the canvas is sampled with when drawn.
+    pub fn sampler(&self) -> SamplerOptions {
+        self.sampler
+    }
 
-        let buffer_view = download_buffer.slice(..).get_mapped_range();
-        let mut v = Vec::with_capacity(byte_width as usize * self.dimensions.y as usize);
-        for y in 0..self.dimensions.y as u64 {
-            let start = y as usize * padded_width as usize;
-            v.extend_from_slice(&buffer_view[start..start + byte_width as usize]);
-        }
-        Ok(v)
+    /// Changes how the canvas is sampled when drawn. Clones of this canvas keep their sampler.
+    pub fn set_sampler(&mut self, ctx: &GraphicsContext, sampler: SamplerOptions) {
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, sampler, "Oblivion_CanvasBindGroup");
+        self.data.bind_group = Rc::new(bind_group);
+        self.sampler = sampler;
+    }
+
+    /// Sets how big the canvas is drawn before the transform is applied, this defaults to `SizeMode::Unit`.
+    pub fn set_size_mode(&mut self, size_mode: SizeMode) {
+        self.size_mode = size_mode;
     }
 
     /// Pushes this canvas to the draw queue.
     pub fn draw(&self, render: &mut Render, transform: Transform) {
-        render.push_data(self.data.clone(), 1, transform, 0);
+        render
+            .push_data(self.data.clone(), 1, transform, STANDARD_PIPELINE)
+            .size = self.size_mode.object_size(mint::Vector2 {
+            x: self.dimensions.x as f32,
+            y: self.dimensions.y as f32,
+        });
     }
 }
@@ -1,8 +1,10 @@
 use std::{num::NonZeroU32, rc::Rc};
 
-use wgpu::util::DeviceExt;
-
-use crate::{GraphicsContext, OblivionError, OblivionResult, PipelineData, Render, Transform};
+use crate::{
+    helpers::{download_texture, mip_level_count, write_texture_region},
+    GraphicsContext, OblivionError, OblivionResult, PipelineData, Rect, Render, SamplerOptions,
+    SizeMode, Transform, STANDARD_PIPELINE,
+};
 
 /// Essentially just a textured rectangle.
 ///
@@ -12,148 +14,385 @@ use crate::{GraphicsContext, OblivionError, OblivionResult, PipelineData, Render
 /// let image_data = image::load_from_memory(image_bytes).unwrap();
 /// let image_rgba = image_data.as_rgba8().unwrap();
 /// let dimensions = image_data.dimensions();
-/// let image = Image::new(ctx, [dimensions.0, dimensions.1], image_rgba);
+/// let image = Image::new(ctx, [dimensions.0, dimensions.1], image_rgba)?;
+/// // Or with the `image` feature:
+/// let image = Image::from_bytes(ctx, image_bytes)?;
 /// /* ... */
 /// image.draw(&mut render, Transform::default());
 /// ```
 #[derive(Clone)]
 pub struct Image {
-    data: PipelineData,
-    real_dim: mint::Vector2<f32>,
-    tex_dim: mint::Vector2<f32>,
+    pub(crate) data: PipelineData,
+    pub(crate) size_mode: SizeMode,
+    pub(crate) tex_dim: mint::Vector2<f32>,
     texture: Rc<wgpu::Texture>,
+    mip_level_count: u32,
+    format: PixelFormat,
+    sampler: SamplerOptions,
+}
+
+/// Layout of the pixel data an `Image` or `ImageBatch` is created from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// Single channel, sampled as `(r, 0, 0, 1)`. Useful for masks read by custom shaders.
+    R8,
+    /// RGBA data that is used as is, such as normal maps or lookup tables.
+    Rgba8Unorm,
+    /// RGBA colors in sRGB space, which is what image files usually contain.
+    #[default]
+    Rgba8UnormSrgb,
+    /// HDR RGBA data as 16 bit floats.
+    Rgba16Float,
+    /// HDR RGBA data as 32 bit floats. Some devices can't filter these,
+    /// creating the image fails with `OblivionError::UnsupportedFormat` there.
+    Rgba32Float,
+    /// BGRA colors in sRGB space, as produced by many video decoders.
+    Bgra8UnormSrgb,
+}
+
+impl PixelFormat {
+    /// Number of bytes a single pixel takes up.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            PixelFormat::R8 => 1,
+            PixelFormat::Rgba8Unorm | PixelFormat::Rgba8UnormSrgb | PixelFormat::Bgra8UnormSrgb => {
+                4
+            }
+            PixelFormat::Rgba16Float => 8,
+            PixelFormat::Rgba32Float => 16,
+        }
+    }
+}
+
+impl From<PixelFormat> for wgpu::TextureFormat {
+    fn from(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::R8 => wgpu::TextureFormat::R8Unorm,
+            PixelFormat::Rgba8Unorm => wgpu::TextureFormat::Rgba8Unorm,
+            PixelFormat::Rgba8UnormSrgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            PixelFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+            PixelFormat::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+            PixelFormat::Bgra8UnormSrgb => wgpu::TextureFormat::Bgra8UnormSrgb,
+        }
+    }
+}
+
+/// Options used when creating an `Image` or `ImageBatch`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ImageOptions {
+    /// Layout of the data the image is created from.
+    pub format: PixelFormat,
+    /// How the texture is sampled, can be changed later with `set_sampler`.
+    pub sampler: SamplerOptions,
+    /// Allocates the full mip chain and fills it by downsampling the image on the GPU.
+    /// This stops images from aliasing when drawn much smaller than their size,
+    /// especially with `SamplerOptions::trilinear`.
+    pub generate_mipmaps: bool,
+}
+
+/// Creates the texture of an `Image` or `ImageBatch` and uploads its data.
+pub(crate) fn create_image_texture(
+    ctx: &GraphicsContext,
+    dimensions: mint::Vector2<u32>,
+    data: &[u8],
+    options: &ImageOptions,
+) -> OblivionResult<wgpu::Texture> {
+    let max_dimension = ctx.device.limits().max_texture_dimension_2d;
+    let expected_len =
+        dimensions.x as u64 * dimensions.y as u64 * options.format.bytes_per_pixel() as u64;
+    if dimensions.x == 0
+        || dimensions.y == 0
+        || dimensions.x > max_dimension
+        || dimensions.y > max_dimension
+        || data.len() as u64 != expected_len
+    {
+        return Err(OblivionError::InvalidImageData {
+            dimensions,
+            format: options.format,
+            len: data.len(),
+        });
+    }
+
+    let format = wgpu::TextureFormat::from(options.format);
+    let features = ctx.format_features(format);
+    let filterable = features.filterable;
+    let renderable = features
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+    // Drawables sample with a filtering sampler, and mipmaps are rendered.
+    if !filterable || (options.generate_mipmaps && !renderable) {
+        return Err(OblivionError::UnsupportedFormat(options.format));
+    }
+
+    let size = wgpu::Extent3d {
+        width: dimensions.x,
+        height: dimensions.y,
+        depth_or_array_layers: 1,
+    };
+    let (mip_level_count, usage) = if options.generate_mipmaps {
+        (
+            mip_level_count(dimensions),
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        )
+    } else {
+        (1, wgpu::TextureUsages::empty())
+    };
+    let texture = ctx.device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC
+            | usage,
+        label: Some("Oblivion_Texture"),
+    });
+    ctx.queue.write_texture(
+        texture.as_image_copy(),
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(dimensions.x * options.format.bytes_per_pixel()),
+            rows_per_image: None,
+        },
+        size,
+    );
+    if mip_level_count > 1 {
+        ctx.generate_mipmaps(&texture, format, dimensions, mip_level_count);
+    }
+    Ok(texture)
 }
 
 impl Image {
-    /// Creates a new image object.
+    /// Creates a new image object from sRGB RGBA data.
     pub fn new(
         ctx: &GraphicsContext,
         dimensions: impl Into<mint::Vector2<u32>>,
         data: &[u8],
-    ) -> Self {
-        let dimensions = dimensions.into();
-        let size = wgpu::Extent3d {
-            width: dimensions.x,
-            height: dimensions.y,
-            depth_or_array_layers: 1,
-        };
-        let texture = ctx.device.create_texture_with_data(
-            &ctx.queue,
-            &wgpu::TextureDescriptor {
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_DST
-                    | wgpu::TextureUsages::COPY_SRC,
-                label: Some("Oblivion_Texture"),
-            },
-            data,
-        );
-
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+    ) -> OblivionResult<Self> {
+        Self::with_options(ctx, dimensions, data, ImageOptions::default())
+    }
 
-        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &ctx.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("Oblivion_ImageBindGroup"),
-        });
+    /// Creates a new image object with custom texture options.
+    pub fn with_options(
+        ctx: &GraphicsContext,
+        dimensions: impl Into<mint::Vector2<u32>>,
+        data: &[u8],
+        options: ImageOptions,
+    ) -> OblivionResult<Self> {
+        let dimensions = dimensions.into();
+        let texture = create_image_texture(ctx, dimensions, data, &options)?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, options.sampler, "Oblivion_ImageBindGroup");
 
-        Image {
+        Ok(Image {
             data: PipelineData {
                 mesh_buffer: Rc::clone(&ctx.quad_mesh_buffer),
                 bind_group: Rc::new(bind_group),
                 instance_buffer: Rc::clone(&ctx.identity_instance_buffer),
                 object_dimensions: mint::Vector2 { x: 1.0, y: 1.0 },
             },
-            real_dim: ctx.gfx_config.render_dimensions,
+            size_mode: SizeMode::default(),
             tex_dim: mint::Vector2 {
                 x: dimensions.x as _,
                 y: dimensions.y as _,
             },
             texture: Rc::new(texture),
-        }
+            mip_level_count: if options.generate_mipmaps {
+                mip_level_count(dimensions)
+            } else {
+                1
+            },
+            format: options.format,
+            sampler: options.sampler,
+        })
+    }
+
+    /// Decodes an image file, such as a PNG or JPEG, from memory.
+    #[cfg(feature = "image")]
+    pub fn from_bytes(ctx: &GraphicsContext, bytes: &[u8]) -> OblivionResult<Self> {
+        Self::from_dynamic_image(ctx, &image::load_from_memory(bytes)?)
     }
 
-    /// Gets the raw RGBA data of this canvas's underlying texture.
+    /// Loads and decodes an image file, the format is guessed from the extension.
+    #[cfg(feature = "image")]
+    pub fn from_path(
+        ctx: &GraphicsContext,
+        path: impl AsRef<std::path::Path>,
+    ) -> OblivionResult<Self> {
+        Self::from_dynamic_image(ctx, &image::open(path)?)
+    }
+
+    /// Creates an image from an already decoded image, converting it to RGBA first.
+    #[cfg(feature = "image")]
+    pub fn from_dynamic_image(
+        ctx: &GraphicsContext,
+        image: &image::DynamicImage,
+    ) -> OblivionResult<Self> {
+        let rgba = image.to_rgba8();
+        let dimensions = [rgba.width(), rgba.height()];
+        Self::with_options(ctx, dimensions, &rgba, ImageOptions::default())
+    }
+
+    /// Gets the layout of the image's pixel data.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Gets the raw data of this image's underlying texture, laid out in its `PixelFormat`.
     pub fn download_rgba(&self, ctx: &mut GraphicsContext) -> OblivionResult<Vec<u8>> {
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
-        let byte_width = self.tex_dim.x as u64 * 4;
-        let padded_width_padding = (align - byte_width % align) % align;
-        let padded_width = byte_width + padded_width_padding;
-
-        let download_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Oblivion_ImageDownloadBuffer"),
-            size: padded_width * self.tex_dim.y as u64,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
-            mapped_at_creation: false,
-        });
-        let mut command_encoder =
-            ctx.device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Oblivion_ImageCommandEncoder"),
-                });
-        command_encoder.copy_texture_to_buffer(
-            wgpu::ImageCopyTexture {
-                texture: &self.texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d { x: 0, y: 0, z: 0 },
-                aspect: wgpu::TextureAspect::All,
-            },
-            wgpu::ImageCopyBuffer {
-                buffer: &download_buffer,
-                layout: wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: NonZeroU32::new(padded_width as u32),
-                    rows_per_image: NonZeroU32::new(self.tex_dim.y as u32),
-                },
-            },
-            wgpu::Extent3d {
-                width: self.tex_dim.x as u32,
-                height: self.tex_dim.y as u32,
-                depth_or_array_layers: 1,
+        download_texture(
+            "Image",
+            &ctx.device,
+            &ctx.queue,
+            &self.texture,
+            mint::Vector2 {
+                x: self.tex_dim.x as u32,
+                y: self.tex_dim.y as u32,
             },
-        );
-        ctx.queue.submit(std::iter::once(command_encoder.finish()));
+            self.format.bytes_per_pixel(),
+        )
+    }
+
+    /// Saves the contents of this image as a PNG file.
+    /// Images with a floating point `PixelFormat` can't be saved.
+    #[cfg(feature = "image")]
+    pub fn save_png(
+        &self,
+        ctx: &mut GraphicsContext,
+        path: impl AsRef<std::path::Path>,
+    ) -> OblivionResult<()> {
+        let dimensions = mint::Vector2 {
+            x: self.tex_dim.x as u32,
+            y: self.tex_dim.y as u32,
+        };
+        let image = match self.format {
+            PixelFormat::R8 => image::DynamicImage::ImageLuma8(
+                image::GrayImage::from_raw(dimensions.x, dimensions.y, self.download_rgba(ctx)?)
+                    .expect("Downloaded texture data matches its dimensions"),
+            ),
+            PixelFormat::Rgba8Unorm | PixelFormat::Rgba8UnormSrgb | PixelFormat::Bgra8UnormSrgb => {
+                image::DynamicImage::ImageRgba8(crate::helpers::rgba_image(
+                    self.format.into(),
+                    dimensions,
+                    self.download_rgba(ctx)?,
+                ))
+            }
+            PixelFormat::Rgba16Float | PixelFormat::Rgba32Float => {
+                return Err(OblivionError::UnsupportedFormat(self.format))
+            }
+        };
+        image
+            .save_with_format(path, image::ImageFormat::Png)
+            .map_err(OblivionError::SaveImage)
+    }
+
+    /// Replaces all pixels of the image, `data` has to be laid out like the data the image was created from.
+    /// The texture is shared with clones of this image, so they change too.
+    pub fn update(&mut self, ctx: &GraphicsContext, data: &[u8]) -> OblivionResult<()> {
+        let region = Rect::new(0.0, 0.0, self.tex_dim.x, self.tex_dim.y);
+        self.update_region(ctx, region, data)
+    }
 
-        let fut = download_buffer.slice(..).map_async(wgpu::MapMode::Read);
-        ctx.device.poll(wgpu::Maintain::Wait);
-        pollster::block_on(fut).map_err(OblivionError::MapBuffer)?;
+    /// Replaces the pixels inside `region`, which is in texture pixels. Mipmaps are regenerated afterwards.
+    pub fn update_region(
+        &mut self,
+        ctx: &GraphicsContext,
+        region: Rect,
+        data: &[u8],
+    ) -> OblivionResult<()> {
+        let whole = |v: f32| v >= 0.0 && v.fract() == 0.0;
+        let inside = region.position.x + region.size.x <= self.tex_dim.x
+            && region.position.y + region.size.y <= self.tex_dim.y;
+        let values = [
+            region.position.x,
+            region.position.y,
+            region.size.x,
+            region.size.y,
+        ];
+        if !values.into_iter().all(whole) || !inside {
+            return Err(OblivionError::InvalidRegion(region));
+        }
+        let origin = mint::Point2 {
+            x: region.position.x as u32,
+            y: region.position.y as u32,
+        };
+        let dimensions = mint::Vector2 {
+            x: region.size.x as u32,
+            y: region.size.y as u32,
+        };
+        let expected_len =
+            dimensions.x as usize * dimensions.y as usize * self.format.bytes_per_pixel() as usize;
+        if data.len() != expected_len {
+            return Err(OblivionError::InvalidImageData {
+                dimensions,
+                format: self.format,
+                len: data.len(),
+            });
+        }
 
-        let buffer_view = download_buffer.slice(..).get_mapped_range();
-        let mut v = Vec::with_capacity(byte_width as usize * self.tex_dim.y as usize);
-        for y in 0..self.tex_dim.y as u64 {
-            let start = y as usize * padded_width as usize;
-            v.extend_from_slice(&buffer_view[start..start + byte_width as usize]);
+        write_texture_region(
+            &ctx.queue,
+            &self.texture,
+            origin,
+            dimensions,
+            self.format.bytes_per_pixel(),
+            data,
+        );
+        if self.mip_level_count > 1 {
+            ctx.generate_mipmaps(
+                &self.texture,
+                self.format.into(),
+                mint::Vector2 {
+                    x: self.tex_dim.x as u32,
+                    y: self.tex_dim.y as u32,
+                },
+                self.mip_level_count,
+            );
         }
-        Ok(v)
+        Ok(())
+    }
+
+    /// Gets the options the texture is sampled with.
+    pub fn sampler(&self) -> SamplerOptions {
+        self.sampler
+    }
+
+    /// Changes how the texture is sampled. Clones of this image keep their sampler.
+    pub fn set_sampler(&mut self, ctx: &GraphicsContext, sampler: SamplerOptions) {
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, sampler, "Oblivion_ImageBindGroup");
+        self.data.bind_group = Rc::new(bind_group);
+        self.sampler = sampler;
+    }
+
+    /// Sets how big the image is drawn before the transform is applied, this defaults to `SizeMode::Render`.
+    pub fn set_size_mode(&mut self, size_mode: SizeMode) {
+        self.size_mode = size_mode;
     }
 
     /// Pushes this image to the draw queue.
     pub fn draw(&self, render: &mut Render, transform: Transform) {
-        let mut transform = transform;
-        transform.scale.x *= self.real_dim.x;
-        transform.scale.y *= self.real_dim.y;
-        render.push_data(self.data.clone(), 1, transform, 0);
+        render
+            .push_data(self.data.clone(), 1, transform, STANDARD_PIPELINE)
+            .size = self.size_mode.object_size(self.tex_dim);
+    }
+
+    /// Pushes part of this image to the draw queue, such as a single frame of a sprite sheet.
+    /// `src` is in texture pixels. With `SizeMode::Natural` the region is drawn at its own size,
+    /// otherwise it's stretched over the same area `draw` would cover.
+    pub fn draw_region(&self, render: &mut Render, src: Rect, transform: Transform) {
+        let draw = render.push_data(self.data.clone(), 1, transform, STANDARD_PIPELINE);
+        draw.src = Rect::new(
+            src.position.x / self.tex_dim.x,
+            src.position.y / self.tex_dim.y,
+            src.size.x / self.tex_dim.x,
+            src.size.y / self.tex_dim.y,
+        );
+        draw.size = self.size_mode.object_size(src.size);
     }
 }
@@ -1,70 +1,69 @@
-use std::rc::Rc;
+use std::{ops::Range, rc::Rc};
 
-use wgpu::util::DeviceExt;
+use super::image::create_image_texture;
+use crate::{
+    GraphicsContext, ImageOptions, InstanceType, OblivionResult, PipelineData, Rect, Render,
+    SamplerOptions, SizeMode, Transform, FULL_SRC, INSTANCE_SIZE, STANDARD_PIPELINE,
+};
 
-use crate::{GraphicsContext, PipelineData, Render, Transform, INSTANCE_SIZE};
+/// A single instance of an `ImageBatch`.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchInstance {
+    pub transform: Transform,
+    /// Part of the texture to draw in texture pixels, the whole texture is drawn if this is `None`.
+    pub src: Option<Rect>,
+    /// Tint of this instance, the color of the transform is used if this is `None`.
+    pub color: Option<rgb::RGBA<f32>>,
+}
+
+impl From<Transform> for BatchInstance {
+    fn from(transform: Transform) -> Self {
+        BatchInstance {
+            transform,
+            src: None,
+            color: None,
+        }
+    }
+}
 
 /// Draws a single texture using many `Transform`s
 #[derive(Clone)]
 pub struct ImageBatch {
     data: PipelineData,
+    /// CPU copy of the instances, so single instances can be changed without re-uploading the rest.
+    instances: Vec<InstanceType>,
+    /// Instances that changed since the last flush, sorted ranges that don't touch.
+    dirty: Vec<Range<usize>>,
     instance_buffer_capacity: u64,
+    /// Number of instances in the instance buffer, this is what gets drawn.
     instance_buffer_count: u64,
-    real_dim: mint::Vector2<f32>,
+    size_mode: SizeMode,
+    tex_dim: mint::Vector2<f32>,
+    view: Rc<wgpu::TextureView>,
+    sampler: SamplerOptions,
 }
 
 impl ImageBatch {
-    /// Creates a new image batch object.
+    /// Creates a new image batch object from sRGB RGBA data.
     pub fn new(
         ctx: &GraphicsContext,
         dimensions: impl Into<mint::Vector2<u32>>,
         data: &[u8],
-    ) -> Self {
-        let dimensions = dimensions.into();
-        let size = wgpu::Extent3d {
-            width: dimensions.x,
-            height: dimensions.y,
-            depth_or_array_layers: 1,
-        };
-        let texture = ctx.device.create_texture_with_data(
-            &ctx.queue,
-            &wgpu::TextureDescriptor {
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                label: Some("Oblivion_Texture"),
-            },
-            data,
-        );
-
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+    ) -> OblivionResult<Self> {
+        Self::with_options(ctx, dimensions, data, ImageOptions::default())
+    }
 
-        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &ctx.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("Oblivion_ImageBindGroup"),
-        });
+    /// Creates a new image batch object with custom texture options.
+    pub fn with_options(
+        ctx: &GraphicsContext,
+        dimensions: impl Into<mint::Vector2<u32>>,
+        data: &[u8],
+        options: ImageOptions,
+    ) -> OblivionResult<Self> {
+        let dimensions = dimensions.into();
+        let texture = create_image_texture(ctx, dimensions, data, &options)?;
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, options.sampler, "Oblivion_ImageBindGroup");
 
         let instance_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Instance Buffer"),
@@ -74,90 +73,306 @@ impl ImageBatch {
         });
         let instance_buffer = Rc::new(instance_buffer);
 
-        ImageBatch {
+        Ok(ImageBatch {
             data: PipelineData {
                 mesh_buffer: Rc::clone(&ctx.quad_mesh_buffer),
                 bind_group: Rc::new(bind_group),
                 instance_buffer: Rc::clone(&instance_buffer),
                 object_dimensions: mint::Vector2 { x: 1.0, y: 1.0 },
             },
+            instances: Vec::new(),
+            dirty: Vec::new(),
             instance_buffer_capacity: 0,
             instance_buffer_count: 0,
-            real_dim: ctx.gfx_config.render_dimensions,
-        }
+            size_mode: SizeMode::default(),
+            tex_dim: mint::Vector2 {
+                x: dimensions.x as _,
+                y: dimensions.y as _,
+            },
+            view: Rc::new(view),
+            sampler: options.sampler,
+        })
+    }
+
+    /// Creates a new image batch object with custom texture options and room for `capacity` instances.
+    pub fn with_capacity(
+        ctx: &mut GraphicsContext,
+        dimensions: impl Into<mint::Vector2<u32>>,
+        data: &[u8],
+        options: ImageOptions,
+        capacity: usize,
+    ) -> OblivionResult<Self> {
+        let mut batch = Self::with_options(ctx, dimensions, data, options)?;
+        batch.reserve(ctx, capacity);
+        Ok(batch)
     }
 
     /// Adds batch instances to the image batch.
     pub fn add_instance(&mut self, ctx: &mut GraphicsContext, transforms: &[Transform]) {
-        let new_count = self.instance_buffer_count + transforms.len() as u64;
+        let instances = transforms
+            .iter()
+            .map(|&transform| BatchInstance::from(transform))
+            .collect::<Vec<_>>();
+        self.add_instance_ex(ctx, &instances);
+    }
+
+    /// Adds batch instances with their own source rectangle or color to the image batch,
+    /// such as particles or the tiles of a tilemap.
+    pub fn add_instance_ex(&mut self, ctx: &mut GraphicsContext, instances: &[BatchInstance]) {
+        let start = self.instances.len();
+        self.instances.reserve(instances.len());
+        for instance in instances {
+            let instance = self.instance_data(instance);
+            self.instances.push(instance);
+        }
+        self.mark_dirty(start..self.instances.len());
+        self.flush(ctx);
+    }
+
+    /// Replaces a single instance. The change is uploaded by the next `flush`.
+    pub fn set_instance(&mut self, idx: usize, instance: impl Into<BatchInstance>) {
+        self.instances[idx] = self.instance_data(&instance.into());
+        self.mark_dirty(idx..idx + 1);
+    }
+
+    /// Replaces a range of instances, `instances` has to be as long as the range.
+    /// The changes are uploaded by the next `flush`.
+    pub fn set_instances<T: Into<BatchInstance> + Copy>(
+        &mut self,
+        range: Range<usize>,
+        instances: &[T],
+    ) {
+        assert_eq!(
+            range.len(),
+            instances.len(),
+            "Range and instances have different lengths"
+        );
+        for (idx, &instance) in range.clone().zip(instances) {
+            self.instances[idx] = self.instance_data(&instance.into());
+        }
+        self.mark_dirty(range);
+    }
+
+    /// Removes an instance by moving the last instance into its place.
+    /// The change is uploaded by the next `flush`.
+    pub fn swap_remove(&mut self, idx: usize) {
+        self.instances.swap_remove(idx);
+        if idx < self.instances.len() {
+            self.mark_dirty(idx..idx + 1);
+        }
+    }
+
+    /// Number of instances, including changes that haven't been flushed yet.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// Number of instances the instance buffer can hold without growing.
+    pub fn capacity(&self) -> usize {
+        self.instance_buffer_capacity as usize
+    }
+
+    /// Makes room for at least `additional` more instances.
+    pub fn reserve(&mut self, ctx: &mut GraphicsContext, additional: usize) {
+        let required = (self.instances.len() + additional) as u64;
+        if required > self.instance_buffer_capacity {
+            self.instances.reserve(additional);
+            self.resize_buffer(ctx, required);
+            self.flush(ctx);
+        }
+    }
+
+    /// Shrinks the instance buffer to the current number of instances.
+    pub fn shrink_to_fit(&mut self, ctx: &mut GraphicsContext) {
+        self.instances.shrink_to_fit();
+        if (self.instances.len() as u64) < self.instance_buffer_capacity {
+            self.resize_buffer(ctx, self.instances.len() as u64);
+        }
+        self.flush(ctx);
+    }
+
+    /// Uploads the instances changed since the last flush.
+    pub fn flush(&mut self, ctx: &mut GraphicsContext) {
+        let new_count = self.instances.len() as u64;
         if new_count > self.instance_buffer_capacity {
-            let new_capacity = new_count;
-            let instance_buffer = Rc::new(ctx.device.create_buffer(&wgpu::BufferDescriptor {
-                label: Some("Oblivion_ImageBatchInstanceBuffer"),
-                usage: wgpu::BufferUsages::VERTEX
-                    | wgpu::BufferUsages::COPY_SRC
-                    | wgpu::BufferUsages::COPY_DST,
-                size: new_capacity * INSTANCE_SIZE as u64,
-                mapped_at_creation: false,
-            }));
-
-            let mut command_encoder =
-                ctx.device
-                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                        label: Some("Oblivion_ImageBatchInstanceBufferResizeCopyCommandEncoder"),
-                    });
-            command_encoder.copy_buffer_to_buffer(
-                &self.data.instance_buffer,
-                0,
-                &instance_buffer,
-                0,
-                self.instance_buffer_count * INSTANCE_SIZE as u64,
-            );
-            ctx.queue.submit(std::iter::once(command_encoder.finish()));
-
-            self.data = PipelineData {
-                instance_buffer: Rc::clone(&instance_buffer),
-                mesh_buffer: Rc::clone(&self.data.mesh_buffer),
-                bind_group: Rc::clone(&self.data.bind_group),
-                object_dimensions: mint::Vector2 { x: 1.0, y: 1.0 },
-            };
-            self.instance_buffer_capacity = new_capacity;
+            // Grow geometrically so adding instances one at a time doesn't reallocate every time.
+            let new_capacity = new_count.max(self.instance_buffer_capacity * 2);
+            self.resize_buffer(ctx, new_capacity);
         }
 
-        let mut transform_data = vec![0; transforms.len() * INSTANCE_SIZE];
-        for (transform, transform_data_slice) in transforms
-            .iter()
-            .zip(transform_data.chunks_exact_mut(INSTANCE_SIZE))
-        {
-            transform_data_slice.copy_from_slice(bytemuck::cast_slice(
-                &transform
-                    .as_matrix(mint::Vector2 { x: 1.0, y: 1.0 })
-                    .to_cols_array_2d(),
-            ));
+        for dirty in self.dirty.drain(..) {
+            let dirty = dirty.start..dirty.end.min(self.instances.len());
+            if !dirty.is_empty() {
+                ctx.queue.write_buffer(
+                    &self.data.instance_buffer,
+                    (dirty.start * INSTANCE_SIZE) as u64,
+                    bytemuck::cast_slice(&self.instances[dirty]),
+                );
+            }
         }
-        ctx.queue.write_buffer(
-            &self.data.instance_buffer,
-            self.instance_buffer_count * INSTANCE_SIZE as u64,
-            &transform_data,
-        );
 
         self.instance_buffer_count = new_count;
     }
 
+    /// Replaces the instance buffer, the instances are uploaded again from the CPU copy on the next flush
+    /// instead of copying the old buffer with a separate submit.
+    fn resize_buffer(&mut self, ctx: &mut GraphicsContext, capacity: u64) {
+        let instance_buffer = Rc::new(ctx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Oblivion_ImageBatchInstanceBuffer"),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            size: capacity * INSTANCE_SIZE as u64,
+            mapped_at_creation: false,
+        }));
+        self.data = PipelineData {
+            instance_buffer: Rc::clone(&instance_buffer),
+            mesh_buffer: Rc::clone(&self.data.mesh_buffer),
+            bind_group: Rc::clone(&self.data.bind_group),
+            object_dimensions: mint::Vector2 { x: 1.0, y: 1.0 },
+        };
+        self.instance_buffer_capacity = capacity;
+        // Nothing in the new buffer can be drawn until it's uploaded.
+        self.instance_buffer_count = 0;
+        self.dirty.clear();
+        self.mark_dirty(0..self.instances.len());
+    }
+
+    /// Adds a range to the dirty ranges, merging it with the ones it overlaps or touches
+    /// so that untouched instances in between aren't uploaded again.
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        // Ranges before `first` end before this range starts, ranges from `last` on start after it ends.
+        let first = self.dirty.partition_point(|dirty| dirty.end < range.start);
+        let last = self.dirty.partition_point(|dirty| dirty.start <= range.end);
+        let merged = match self.dirty[first..last] {
+            [] => range,
+            ref touched => {
+                touched[0].start.min(range.start)..touched[touched.len() - 1].end.max(range.end)
+            }
+        };
+        self.dirty.splice(first..last, std::iter::once(merged));
+    }
+
     pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty.clear();
         self.instance_buffer_count = 0;
     }
 
-    /// Pushes this image batch to the draw queue.
+    fn instance_data(&self, instance: &BatchInstance) -> InstanceType {
+        let src = match instance.src {
+            Some(src) => Rect::new(
+                src.position.x / self.tex_dim.x,
+                src.position.y / self.tex_dim.y,
+                src.size.x / self.tex_dim.x,
+                src.size.y / self.tex_dim.y,
+            ),
+            None => FULL_SRC,
+        };
+        InstanceType::new(
+            instance
+                .transform
+                .as_matrix(mint::Vector2 { x: 1.0, y: 1.0 }),
+            src,
+            instance.color.unwrap_or(instance.transform.color),
+        )
+    }
+
+    /// Gets the options the texture is sampled with.
+    pub fn sampler(&self) -> SamplerOptions {
+        self.sampler
+    }
+
+    /// Changes how the texture is sampled.
+    pub fn set_sampler(&mut self, ctx: &GraphicsContext, sampler: SamplerOptions) {
+        let bind_group = ctx.texture_bind_group(&self.view, sampler, "Oblivion_ImageBindGroup");
+        self.data.bind_group = Rc::new(bind_group);
+        self.sampler = sampler;
+    }
+
+    /// Sets the size of a single unit of the batch, which is what instances are positioned and scaled in.
+    /// This defaults to `SizeMode::Render`.
+    pub fn set_size_mode(&mut self, size_mode: SizeMode) {
+        self.size_mode = size_mode;
+    }
+
+    /// Pushes this image batch to the draw queue, changes to instances have to be flushed first.
+    /// The color of the transform tints the whole batch on top of each instance's own color.
+    ///
+    /// Drawing with unflushed changes panics in debug builds, the GPU would still draw the old instances.
     pub fn draw(&self, render: &mut Render, transform: Transform) {
-        let mut transform = transform;
-        transform.scale.x *= self.real_dim.x;
-        transform.scale.y *= self.real_dim.y;
-        render.push_data(
-            self.data.clone(),
-            self.instance_buffer_count as u32,
-            transform,
-            0,
+        debug_assert!(
+            self.dirty.is_empty() && self.instance_buffer_count == self.instances.len() as u64,
+            "Image batch changes have to be flushed before drawing"
         );
+        render
+            .push_data(
+                self.data.clone(),
+                self.instance_buffer_count as u32,
+                transform,
+                STANDARD_PIPELINE,
+            )
+            .size = self.size_mode.object_size(self.tex_dim);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GraphicsConfig;
+
+    #[test]
+    fn dirty_ranges_only_merge_when_touching() {
+        let mut ctx = GraphicsContext::new_headless(
+            [32, 32],
+            GraphicsConfig {
+                vsync: false,
+                render_dimensions: [1.0, 1.0].into(),
+            },
+        )
+        .unwrap_or_else(|e| panic!("Creating a headless context failed: {}", e));
+        let mut batch = ImageBatch::new(&ctx, [1, 1], &[255; 4]).unwrap();
+        batch.add_instance(&mut ctx, &[Transform::default(); 100]);
+        assert!(batch.dirty.is_empty());
+
+        batch.set_instance(90, Transform::default());
+        batch.set_instance(10, Transform::default());
+        batch.set_instances(50..52, &[Transform::default(); 2]);
+        assert_eq!(batch.dirty, [10..11, 50..52, 90..91]);
+        // Adjacent to 50..52 and overlapping 90..91.
+        batch.set_instance(52, Transform::default());
+        batch.set_instances(85..95, &[Transform::default(); 10]);
+        assert_eq!(batch.dirty, [10..11, 50..53, 85..95]);
+        // Bridges the gap between two ranges.
+        batch.set_instances(11..50, &[Transform::default(); 39]);
+        assert_eq!(batch.dirty, [10..53, 85..95]);
+
+        batch.flush(&mut ctx);
+        assert!(batch.dirty.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "flushed before drawing")]
+    fn drawing_unflushed_removal_panics() {
+        let mut ctx = GraphicsContext::new_headless(
+            [32, 32],
+            GraphicsConfig {
+                vsync: false,
+                render_dimensions: [1.0, 1.0].into(),
+            },
+        )
+        .unwrap_or_else(|e| panic!("Creating a headless context failed: {}", e));
+        let mut batch = ImageBatch::new(&ctx, [1, 1], &[255; 4]).unwrap();
+        batch.add_instance(&mut ctx, &[Transform::default(); 2]);
+        let mut render = Render::new();
+        batch.draw(&mut render, Transform::default());
+        // Removing the last instance doesn't dirty anything, only the count changes.
+        batch.swap_remove(1);
+        batch.draw(&mut render, Transform::default());
     }
 }
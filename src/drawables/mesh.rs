@@ -2,7 +2,10 @@ use std::rc::Rc;
 
 use wgpu::util::DeviceExt;
 
-use crate::{GraphicsContext, MeshBuffer, OblivionResult, PipelineData, Render, Transform, Vertex};
+use crate::{
+    GraphicsContext, MeshBuffer, OblivionResult, PipelineData, Render, SamplerOptions, Transform,
+    Vertex, STANDARD_PIPELINE,
+};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 struct VertexBuilder {
@@ -79,8 +82,8 @@ impl MeshBuilder {
         let position = position.into();
         let size = size.into();
         let color = color.into();
-        let mut buffers = &mut self.buffers;
-        let mut bb = lyon::tessellation::BuffersBuilder::new(&mut buffers, VertexBuilder { color });
+        let buffers = &mut self.buffers;
+        let mut bb = lyon::tessellation::BuffersBuilder::new(buffers, VertexBuilder { color });
         match mode {
             DrawMode::Fill => {
                 let mut tessellator = lyon::tessellation::FillTessellator::new();
@@ -140,8 +143,8 @@ impl MeshBuilder {
         let position = position.into();
         let size = size.into();
         let color = color.into();
-        let mut buffers = &mut self.buffers;
-        let mut bb = lyon::tessellation::BuffersBuilder::new(&mut buffers, VertexBuilder { color });
+        let buffers = &mut self.buffers;
+        let mut bb = lyon::tessellation::BuffersBuilder::new(buffers, VertexBuilder { color });
         match mode {
             DrawMode::Fill => {
                 let mut tessellator = lyon::tessellation::FillTessellator::new();
@@ -184,8 +187,8 @@ impl MeshBuilder {
         let position = position.into();
         let size = size.into();
         let color = color.into();
-        let mut buffers = &mut self.buffers;
-        let mut bb = lyon::tessellation::BuffersBuilder::new(&mut buffers, VertexBuilder { color });
+        let buffers = &mut self.buffers;
+        let mut bb = lyon::tessellation::BuffersBuilder::new(buffers, VertexBuilder { color });
         match mode {
             DrawMode::Fill => {
                 let mut tessellator = lyon::tessellation::FillTessellator::new();
@@ -238,8 +241,8 @@ impl MeshBuilder {
         path.end(false);
         let path = path.build();
 
-        let mut buffers = &mut self.buffers;
-        let mut bb = lyon::tessellation::BuffersBuilder::new(&mut buffers, VertexBuilder { color });
+        let buffers = &mut self.buffers;
+        let mut bb = lyon::tessellation::BuffersBuilder::new(buffers, VertexBuilder { color });
         let mut tessellator = lyon::tessellation::StrokeTessellator::new();
         tessellator
             .tessellate_path(
@@ -267,15 +270,33 @@ impl MeshBuilder {
     }
 }
 
+impl Default for MeshBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Renderable mesh object. Essentially a list of shapes.
 #[derive(Clone)]
 pub struct Mesh {
-    data: PipelineData,
+    pub(crate) data: PipelineData,
+    view: Rc<wgpu::TextureView>,
+    sampler: SamplerOptions,
 }
 
 impl Mesh {
     /// Creates a new mesh object.
     pub fn new(ctx: &GraphicsContext, vertex: &[Vertex], index: &[u16]) -> Self {
+        Self::with_sampler(ctx, vertex, index, SamplerOptions::default())
+    }
+
+    /// Creates a new mesh object with custom sampler options.
+    pub fn with_sampler(
+        ctx: &GraphicsContext,
+        vertex: &[Vertex],
+        index: &[u16],
+        sampler: SamplerOptions,
+    ) -> Self {
         let mesh_buffer = MeshBuffer::from_slices(&ctx.device, vertex, index);
 
         // TODO move into context
@@ -297,31 +318,8 @@ impl Mesh {
             &[255, 255, 255, 255],
         );
 
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
-        let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &ctx.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: Some("Oblivion_MeshTextureBindGroup"),
-        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, sampler, "Oblivion_MeshTextureBindGroup");
 
         let min_point = vertex.iter().fold(
             mint::Point2 {
@@ -356,10 +354,25 @@ impl Mesh {
                 instance_buffer: Rc::clone(&ctx.identity_instance_buffer),
                 object_dimensions,
             },
+            view: Rc::new(view),
+            sampler,
         }
     }
 
+    /// Gets the options the mesh texture is sampled with.
+    pub fn sampler(&self) -> SamplerOptions {
+        self.sampler
+    }
+
+    /// Changes how the mesh texture is sampled.
+    pub fn set_sampler(&mut self, ctx: &GraphicsContext, sampler: SamplerOptions) {
+        let bind_group =
+            ctx.texture_bind_group(&self.view, sampler, "Oblivion_MeshTextureBindGroup");
+        self.data.bind_group = Rc::new(bind_group);
+        self.sampler = sampler;
+    }
+
     pub fn draw(&self, render: &mut Render, transform: Transform) {
-        render.push_data(self.data.clone(), 1, transform, 0);
+        render.push_data(self.data.clone(), 1, transform, STANDARD_PIPELINE);
     }
 }
@@ -1,3 +1,4 @@
+mod atlas;
 mod canvas;
 mod image;
 mod image_batch;
@@ -5,6 +6,7 @@ mod mesh;
 mod text;
 
 pub use self::image::*;
+pub use atlas::*;
 pub use canvas::*;
 pub use image_batch::*;
 pub use mesh::*;
@@ -1,10 +1,10 @@
-use std::{num::NonZeroU32, rc::Rc};
+use std::rc::Rc;
 
 use glyph_brush::{ab_glyph::FontArc, FontId, GlyphBrush, GlyphCruncher, Section};
 
 use crate::{
-    GraphicsContext, MeshBuffer, OblivionError, OblivionResult, PipelineData, Render, Transform,
-    Vertex,
+    helpers::write_texture_region, GraphicsContext, MeshBuffer, ObjectSize, OblivionError,
+    OblivionResult, PipelineData, Render, SamplerOptions, Transform, Vertex, TEXT_PIPELINE,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -56,28 +56,25 @@ impl From<String> for TextFragment {
 pub struct Text {
     pipeline_data: PipelineData,
     texture: Rc<wgpu::Texture>,
-    upload_buffer: Rc<wgpu::Buffer>,
-    upload_buffer_size: wgpu::BufferAddress,
     fragments: Vec<TextFragment>,
     dirty: bool,
     bounds: (mint::Point2<f32>, mint::Vector2<f32>),
     tex_dim: (u32, u32),
+    sampler: SamplerOptions,
 }
 
 impl Text {
     /// Creates a new text object.
     pub fn new(ctx: &mut GraphicsContext) -> Self {
+        Self::with_sampler(ctx, SamplerOptions::default())
+    }
+
+    /// Creates a new text object whose glyphs are sampled with custom options.
+    pub fn with_sampler(ctx: &mut GraphicsContext, sampler: SamplerOptions) -> Self {
         let mesh_buffer = MeshBuffer::from_slices(&ctx.device, &[], &[]);
 
-        let upload_buffer_size = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress * 100;
-        let upload_buffer = ctx.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Oblivion_TextUploadBuffer"),
-            size: upload_buffer_size,
-            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::MAP_WRITE,
-            mapped_at_creation: false,
-        });
         let texture_dimensions = ctx.glyph_brush.texture_dimensions();
-        let (texture, bind_group) = create_texture(ctx, texture_dimensions);
+        let (texture, bind_group) = create_texture(ctx, texture_dimensions, sampler);
         Text {
             pipeline_data: PipelineData {
                 mesh_buffer: Rc::new(mesh_buffer),
@@ -86,8 +83,6 @@ impl Text {
                 object_dimensions: mint::Vector2 { x: 0.0, y: 0.0 },
             },
             texture: Rc::new(texture),
-            upload_buffer: Rc::new(upload_buffer),
-            upload_buffer_size,
             fragments: Vec::new(),
             dirty: false,
             bounds: (
@@ -95,14 +90,14 @@ impl Text {
                 mint::Vector2 { x: 0.0, y: 0.0 },
             ),
             tex_dim: texture_dimensions,
+            sampler,
         }
     }
 
     // Make a add_text + flush method instead?
     /// Adds texts to the text object.
     pub fn add_text<const N: usize>(&mut self, frags: [impl Into<TextFragment>; N]) {
-        self.fragments
-            .extend(frags.map(|frag| frag.into()).into_iter());
+        self.fragments.extend(frags.map(|frag| frag.into()));
         self.dirty = true;
     }
 
@@ -110,6 +105,7 @@ impl Text {
         self.fragments.clear();
     }
 
+    /// Gets the bounds in the screen's render units, as of the last flush.
     pub fn bounds(&self) -> (mint::Point2<f32>, mint::Vector2<f32>) {
         self.bounds
     }
@@ -150,7 +146,8 @@ impl Text {
         let pos = [bounds.0.x * norm_vec.x, bounds.0.y * norm_vec.y].into();
         let size = [bounds.1.x * norm_vec.x, bounds.1.y * norm_vec.y].into();
         self.bounds = (pos, size);
-        self.pipeline_data.object_dimensions = size;
+        // The vertices stay in pixels, they are scaled to the render group when submitting.
+        self.pipeline_data.object_dimensions = bounds.1;
         ctx.glyph_brush.queue(section);
 
         let mut retry = true;
@@ -160,84 +157,20 @@ impl Text {
                 .resize_texture(self.tex_dim.0, self.tex_dim.1);
             match ctx.glyph_brush.process_queued(
                 |rect, tex_data| {
-                    let [width, height] = [
-                        rect.width() as wgpu::BufferAddress,
-                        rect.height() as wgpu::BufferAddress,
-                    ];
-                    let offset = [
-                        rect.min[0] as wgpu::BufferAddress,
-                        rect.min[1] as wgpu::BufferAddress,
-                    ];
-
-                    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
-                    let padded_width_padding = (align - width % align) % align;
-                    let padded_width = width + padded_width_padding;
-
-                    let padded_data_size = padded_width * height;
-                    if self.upload_buffer_size < padded_data_size {
-                        self.upload_buffer =
-                            Rc::new(ctx.device.create_buffer(&wgpu::BufferDescriptor {
-                                label: Some("Oblivion_TextUploadBuffer"),
-                                size: padded_data_size,
-                                usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::MAP_WRITE,
-                                mapped_at_creation: false,
-                            }));
-
-                        self.upload_buffer_size = padded_data_size;
-                    }
-
-                    let fut = self
-                        .upload_buffer
-                        .slice(..padded_data_size)
-                        .map_async(wgpu::MapMode::Write);
-                    ctx.device.poll(wgpu::Maintain::Wait);
-                    pollster::block_on(fut)
-                        .map_err(OblivionError::MapBuffer)
-                        .unwrap();
-
-                    for row in 0..height {
-                        self.upload_buffer
-                            .slice(row * padded_width..(row + 1) * padded_width)
-                            .get_mapped_range_mut()[0..width as usize]
-                            .copy_from_slice(
-                                &tex_data[row as usize * width as usize
-                                    ..(row as usize + 1) * width as usize],
-                            )
-                    }
-
-                    self.upload_buffer.unmap();
-
-                    let mut command_encoder =
-                        ctx.device
-                            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                                label: Some("Oblivion_TextCommandEncoder"),
-                            });
-                    command_encoder.copy_buffer_to_texture(
-                        wgpu::ImageCopyBuffer {
-                            buffer: &self.upload_buffer,
-                            layout: wgpu::ImageDataLayout {
-                                offset: 0,
-                                bytes_per_row: NonZeroU32::new(padded_width as u32),
-                                rows_per_image: NonZeroU32::new(height as u32),
-                            },
-                        },
-                        wgpu::ImageCopyTexture {
-                            texture: &self.texture,
-                            mip_level: 0,
-                            origin: wgpu::Origin3d {
-                                x: offset[0] as u32,
-                                y: offset[1] as u32,
-                                z: 0,
-                            },
-                            aspect: wgpu::TextureAspect::All,
+                    write_texture_region(
+                        &ctx.queue,
+                        &self.texture,
+                        mint::Point2 {
+                            x: rect.min[0],
+                            y: rect.min[1],
                         },
-                        wgpu::Extent3d {
-                            width: width as u32,
-                            height: height as u32,
-                            depth_or_array_layers: 1,
+                        mint::Vector2 {
+                            x: rect.width(),
+                            y: rect.height(),
                         },
+                        1,
+                        tex_data,
                     );
-                    ctx.queue.submit(std::iter::once(command_encoder.finish()));
                 },
                 |vertex_data| {
                     let color = vertex_data.extra.color.into();
@@ -285,18 +218,7 @@ impl Text {
                             })
                             .flatten()
                             .collect::<Vec<_>>();
-                        let vertices = vertices_list
-                            .into_iter()
-                            .map(|vertex_list| {
-                                vertex_list.map(|mut v| {
-                                    v.position =
-                                        [v.position.x * norm_vec.x, v.position.y * norm_vec.y]
-                                            .into();
-                                    v
-                                })
-                            })
-                            .flatten()
-                            .collect::<Vec<_>>();
+                        let vertices = vertices_list.into_iter().flatten().collect::<Vec<_>>();
                         let mesh_buffer = MeshBuffer::from_slices(&ctx.device, &vertices, &indices);
                         self.pipeline_data.mesh_buffer = Rc::new(mesh_buffer);
                     }
@@ -318,24 +240,43 @@ impl Text {
     }
 
     fn resize_texture(&mut self, ctx: &mut GraphicsContext, dimensions: (u32, u32)) {
-        let (texture, bind_group) = create_texture(ctx, dimensions);
+        let (texture, bind_group) = create_texture(ctx, dimensions, self.sampler);
         self.texture = Rc::new(texture);
         self.pipeline_data.bind_group = Rc::new(bind_group);
         self.tex_dim = dimensions;
     }
 
+    /// Gets the options the glyphs are sampled with.
+    pub fn sampler(&self) -> SamplerOptions {
+        self.sampler
+    }
+
+    /// Changes how the glyphs are sampled.
+    pub fn set_sampler(&mut self, ctx: &GraphicsContext, sampler: SamplerOptions) {
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = ctx.texture_bind_group(&view, sampler, "Oblivion_TextBindGroup");
+        self.pipeline_data.bind_group = Rc::new(bind_group);
+        self.sampler = sampler;
+    }
+
     /// Pushes this text object to the draw queue.
     pub fn draw(&self, render: &mut Render, transform: Transform) {
         if self.dirty {
             panic!("Call Text::flush before draw!");
         }
-        render.push_data(self.pipeline_data.clone(), 1, transform, 1);
+        // Glyphs are rasterized at their pixel size, so each unit covers one pixel of the render target.
+        render
+            .push_data(self.pipeline_data.clone(), 1, transform, TEXT_PIPELINE)
+            .size = ObjectSize::Texels(mint::Vector2 { x: 1.0, y: 1.0 });
     }
 }
 
 fn create_texture(
     ctx: &mut GraphicsContext,
     dimensions: (u32, u32),
+    sampler: SamplerOptions,
 ) -> (wgpu::Texture, wgpu::BindGroup) {
     let texture = ctx.device.create_texture(&wgpu::TextureDescriptor {
         label: Some("Oblivion_TextTexture"),
@@ -351,31 +292,11 @@ fn create_texture(
         sample_count: 1,
     });
 
-    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-    let sampler = ctx.device.create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: wgpu::AddressMode::ClampToEdge,
-        address_mode_v: wgpu::AddressMode::ClampToEdge,
-        address_mode_w: wgpu::AddressMode::ClampToEdge,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
-        ..Default::default()
-    });
-
-    let bind_group = ctx.device.create_bind_group(&wgpu::BindGroupDescriptor {
-        layout: &ctx.texture_bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-        ],
-        label: Some("Oblivion_ImageBindGroup"),
-    });
+    let bind_group = ctx.texture_bind_group(
+        &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+        sampler,
+        "Oblivion_TextBindGroup",
+    );
 
     (texture, bind_group)
 }
@@ -2,7 +2,10 @@
 pub type OblivionResult<T> = Result<T, OblivionError>;
 
 /// Errors that can occur in Oblivion.
+///
+/// Some variants only exist with the matching cargo feature enabled, so the enum is non-exhaustive.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum OblivionError {
     #[error("Unable to find a valid adapter card.")]
     RequestAdapter,
@@ -16,6 +19,51 @@ pub enum OblivionError {
     InvalidSurface,
     #[error("Error occured while retrieving render frame.")]
     RetrieveFrameError(#[from] wgpu::SurfaceError),
+    #[error("Graphics context is not headless.")]
+    NotHeadless,
+    #[error("Render refers to a canvas or shader that has been dropped.")]
+    StaleHandle,
+    #[error("Image data of {len} bytes doesn't match {dimensions:?} pixels of {format:?}.")]
+    InvalidImageData {
+        dimensions: mint::Vector2<u32>,
+        format: crate::PixelFormat,
+        len: usize,
+    },
+    #[error("Region {0:?} isn't made of whole pixels inside the image.")]
+    InvalidRegion(crate::Rect),
+    #[error("Pixel format {0:?} can't be used for this image on this graphics device.")]
+    UnsupportedFormat(crate::PixelFormat),
+    #[cfg(feature = "image")]
+    #[error("Unable to load image.")]
+    LoadImage(#[from] image::ImageError),
+    #[cfg(feature = "image")]
+    #[error("Unable to save image.")]
+    SaveImage(image::ImageError),
+    #[cfg(feature = "image")]
+    #[error("No frame has been captured, enable frame capture before submitting a render.")]
+    NoCapturedFrame,
+    #[cfg(feature = "json")]
+    #[error("Unable to parse sprite sheet JSON.")]
+    ParseSpriteSheet(#[from] serde_json::Error),
+    #[cfg(feature = "json")]
+    #[error(
+        "Sprite sheet tag {name:?} uses frames {from} to {to}, but there are {frame_count} frames."
+    )]
+    InvalidSpriteSheetTag {
+        name: String,
+        from: usize,
+        to: usize,
+        frame_count: usize,
+    },
+    #[cfg(feature = "golden")]
+    #[error("Rendered frame differs from golden image {reference:?} in {mismatched} pixels.")]
+    GoldenMismatch {
+        reference: std::path::PathBuf,
+        mismatched: usize,
+    },
+    #[cfg(feature = "golden")]
+    #[error("Unable to read or write golden image {0:?}.")]
+    GoldenImage(std::path::PathBuf),
     //#[error("Failed to tesselate shape.")]
     //TessellationError(#[from] lyon::tessellation::TessellationError),
 }
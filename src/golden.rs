@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+
+use crate::{GraphicsContext, OblivionError, OblivionResult, Render};
+
+/// Environment variable that makes `compare_golden` overwrite the reference images instead of comparing.
+pub const BLESS_ENV: &str = "OBLIVION_BLESS";
+
+/// Result of comparing two RGBA buffers with `compare_rgba`.
+pub struct GoldenComparison {
+    /// Number of pixels where at least one channel differs by more than the tolerance.
+    pub mismatched: usize,
+    /// RGBA image highlighting mismatched pixels in red over a dimmed copy of the expected image.
+    pub diff: Vec<u8>,
+}
+
+/// Compares two RGBA buffers of the same dimensions, allowing each channel to differ by `tolerance`.
+pub fn compare_rgba(actual: &[u8], expected: &[u8], tolerance: u8) -> GoldenComparison {
+    let mut mismatched = 0;
+    let mut diff = Vec::with_capacity(expected.len());
+    for (actual, expected) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let matches = actual
+            .iter()
+            .zip(expected)
+            .all(|(&a, &e)| a.abs_diff(e) <= tolerance);
+        if matches {
+            let luma = (expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 3;
+            let dimmed = (luma / 4) as u8;
+            diff.extend_from_slice(&[dimmed, dimmed, dimmed, 255]);
+        } else {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        }
+    }
+    GoldenComparison { mismatched, diff }
+}
+
+/// Submits the render to a headless context and compares the screen against the PNG at `reference`.
+///
+/// On mismatch `<reference>.actual.png` and `<reference>.diff.png` are written next to the reference.
+/// If the `OBLIVION_BLESS` environment variable is set, the reference is (re)created instead.
+///
+/// Example usage:
+/// ```rust
+/// let mut ctx = GraphicsContext::new_headless([320, 180], gfx_config)?;
+/// /* ... */
+/// oblivion::golden::compare_golden(&mut ctx, &render, "tests/golden/draw_mesh.png", 2)?;
+/// ```
+pub fn compare_golden(
+    ctx: &mut GraphicsContext,
+    render: &Render,
+    reference: impl AsRef<Path>,
+    tolerance: u8,
+) -> OblivionResult<()> {
+    let reference = reference.as_ref();
+    ctx.submit_render(render)?;
+    let actual = ctx.download_screen_rgba()?;
+    let dimensions = ctx.surface_dimensions();
+
+    if std::env::var_os(BLESS_ENV).is_some() {
+        return save_png(reference, dimensions, &actual);
+    }
+
+    let expected = image::open(reference)
+        .map_err(|_| OblivionError::GoldenImage(reference.to_owned()))?
+        .to_rgba8();
+    let actual_path = sibling_path(reference, "actual");
+    if expected.dimensions() != (dimensions.x, dimensions.y) {
+        save_png(&actual_path, dimensions, &actual)?;
+        return Err(OblivionError::GoldenMismatch {
+            reference: reference.to_owned(),
+            mismatched: (dimensions.x * dimensions.y) as usize,
+        });
+    }
+
+    let comparison = compare_rgba(&actual, expected.as_raw(), tolerance);
+    if comparison.mismatched > 0 {
+        save_png(&actual_path, dimensions, &actual)?;
+        save_png(
+            &sibling_path(reference, "diff"),
+            dimensions,
+            &comparison.diff,
+        )?;
+        return Err(OblivionError::GoldenMismatch {
+            reference: reference.to_owned(),
+            mismatched: comparison.mismatched,
+        });
+    }
+    Ok(())
+}
+
+fn sibling_path(reference: &Path, suffix: &str) -> PathBuf {
+    reference.with_extension(format!("{}.png", suffix))
+}
+
+fn save_png(path: &Path, dimensions: mint::Vector2<u32>, data: &[u8]) -> OblivionResult<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|_| OblivionError::GoldenImage(path.to_owned()))?;
+    }
+    image::save_buffer(
+        path,
+        data,
+        dimensions.x,
+        dimensions.y,
+        image::ColorType::Rgba8,
+    )
+    .map_err(|_| OblivionError::GoldenImage(path.to_owned()))
+}
@@ -1,10 +1,14 @@
+use std::{collections::HashMap, num::NonZeroU32};
+
 use pollster::block_on;
 use wgpu::{
     Adapter, Backends, Device, DeviceDescriptor, Features, Limits, Queue, RequestAdapterOptions,
     Surface,
 };
 
-use crate::{instance_desc, OblivionError, OblivionResult, Vertex};
+use crate::{
+    instance_desc, OblivionError, OblivionResult, PipelineVariant, ShaderPipelines, Vertex,
+};
 
 // TODO Result
 pub fn get_adapter_surface(
@@ -22,12 +26,28 @@ pub fn get_adapter_surface(
     Ok((adapter, surface))
 }
 
+pub fn get_headless_adapter() -> OblivionResult<Adapter> {
+    let instance = wgpu::Instance::new(Backends::all());
+    let request = |force_fallback_adapter| {
+        block_on(instance.request_adapter(&RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter,
+            compatible_surface: None,
+        }))
+    };
+    // Machines without a display often only have a software adapter.
+    request(false)
+        .or_else(|| request(true))
+        .ok_or(OblivionError::RequestAdapter)
+}
+
 // TODO Result
 pub fn get_device_queue(adapter: &Adapter) -> OblivionResult<(Device, Queue)> {
     let (device, queue) = block_on(adapter.request_device(
         &DeviceDescriptor {
             label: Some("Oblivion_Device"),
-            features: Features::default(),
+            // Lets formats such as `Rgba32Float` be filtered where the adapter supports it.
+            features: adapter.features() & Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
             limits: Limits::default(),
         },
         None,
@@ -36,40 +56,63 @@ pub fn get_device_queue(adapter: &Adapter) -> OblivionResult<(Device, Queue)> {
     Ok((device, queue))
 }
 
-pub fn create_pipeline(
-    name: &str,
+pub fn create_pipeline_layout(
     device: &Device,
-    format: wgpu::TextureFormat,
-    source: wgpu::ShaderSource,
     texture_bind_group_layout: &wgpu::BindGroupLayout,
     mvp_bind_group_layout: &wgpu::BindGroupLayout,
-) -> wgpu::RenderPipeline {
+) -> wgpu::PipelineLayout {
+    device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Oblivion_RenderPipelineLayout"),
+        bind_group_layouts: &[texture_bind_group_layout, mvp_bind_group_layout],
+        push_constant_ranges: &[],
+    })
+}
+
+pub fn create_shader_pipelines(
+    name: &str,
+    device: &Device,
+    source: wgpu::ShaderSource,
+    premultiplied_entry_point: Option<&'static str>,
+) -> ShaderPipelines {
     let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
         label: Some(&format!("Oblivion_{}Shader", name)),
         source,
     });
+    ShaderPipelines {
+        name: name.to_owned(),
+        shader,
+        premultiplied_entry_point,
+        variants: HashMap::new(),
+    }
+}
 
-    let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: Some(&format!("Oblivion_{}RenderPipelineLayout", name)),
-        bind_group_layouts: &[texture_bind_group_layout, mvp_bind_group_layout],
-        push_constant_ranges: &[],
-    });
-
+pub fn create_pipeline(
+    name: &str,
+    device: &Device,
+    format: wgpu::TextureFormat,
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    variant: PipelineVariant,
+) -> wgpu::RenderPipeline {
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some(&format!("Oblivion_{}RenderPipeline", name)),
-        layout: Some(&render_pipeline_layout),
+        label: Some(&format!(
+            "Oblivion_{}{:?}{:?}RenderPipeline",
+            name, variant.blend_mode, variant.stencil_mode
+        )),
+        layout: Some(render_pipeline_layout),
         vertex: wgpu::VertexState {
-            module: &shader,
+            module: shader,
             entry_point: "vs_main",
             buffers: &[Vertex::desc(), instance_desc()],
         },
         fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: "fs_main",
+            module: shader,
+            entry_point: fragment_entry_point,
             targets: &[wgpu::ColorTargetState {
                 format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                write_mask: wgpu::ColorWrites::ALL,
+                blend: Some(variant.blend_mode.blend_state()),
+                write_mask: variant.stencil_mode.color_writes(),
             }],
         }),
         primitive: wgpu::PrimitiveState {
@@ -82,7 +125,7 @@ pub fn create_pipeline(
             conservative: false,
         },
         multiview: None,
-        depth_stencil: None,
+        depth_stencil: variant.stencil_mode.depth_stencil_state(),
         multisample: wgpu::MultisampleState {
             count: 1,
             mask: !0,
@@ -92,3 +135,154 @@ pub fn create_pipeline(
 
     render_pipeline
 }
+
+/// Creates the pipeline that renders each mip level from the previous one.
+pub fn create_mipmap_pipeline(
+    device: &Device,
+    format: wgpu::TextureFormat,
+    shader: &wgpu::ShaderModule,
+    layout: &wgpu::PipelineLayout,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(&format!("Oblivion_Mipmap{:?}RenderPipeline", format)),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[format.into()],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+/// Number of mip levels needed to go from these dimensions down to a single pixel.
+pub fn mip_level_count(dimensions: mint::Vector2<u32>) -> u32 {
+    32 - dimensions.x.max(dimensions.y).max(1).leading_zeros()
+}
+
+/// Pads the bytes of a row to `COPY_BYTES_PER_ROW_ALIGNMENT`, as copies between buffers and textures require.
+pub fn padded_bytes_per_row(bytes_per_row: u64) -> u64 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+    bytes_per_row.div_ceil(align) * align
+}
+
+/// Writes tightly packed pixel data into the area of the first mip level starting at `origin`.
+pub fn write_texture_region(
+    queue: &Queue,
+    texture: &wgpu::Texture,
+    origin: mint::Point2<u32>,
+    dimensions: mint::Vector2<u32>,
+    bytes_per_pixel: u32,
+    data: &[u8],
+) {
+    if dimensions.x == 0 || dimensions.y == 0 {
+        return;
+    }
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: origin.x,
+                y: origin.y,
+                z: 0,
+            },
+            aspect: wgpu::TextureAspect::All,
+        },
+        data,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(dimensions.x * bytes_per_pixel),
+            rows_per_image: None,
+        },
+        wgpu::Extent3d {
+            width: dimensions.x,
+            height: dimensions.y,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Copies a texture into a tightly packed buffer.
+pub fn download_texture(
+    name: &str,
+    device: &Device,
+    queue: &Queue,
+    texture: &wgpu::Texture,
+    dimensions: mint::Vector2<u32>,
+    bytes_per_pixel: u32,
+) -> OblivionResult<Vec<u8>> {
+    let byte_width = dimensions.x as u64 * bytes_per_pixel as u64;
+    let padded_width = padded_bytes_per_row(byte_width);
+
+    let download_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("Oblivion_{}DownloadBuffer", name)),
+        size: padded_width * dimensions.y as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut command_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some(&format!("Oblivion_{}CommandEncoder", name)),
+    });
+    command_encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &download_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_width as u32),
+                rows_per_image: NonZeroU32::new(dimensions.y),
+            },
+        },
+        wgpu::Extent3d {
+            width: dimensions.x,
+            height: dimensions.y,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(command_encoder.finish()));
+
+    let fut = download_buffer.slice(..).map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    block_on(fut).map_err(OblivionError::MapBuffer)?;
+
+    let buffer_view = download_buffer.slice(..).get_mapped_range();
+    let mut v = Vec::with_capacity(byte_width as usize * dimensions.y as usize);
+    for y in 0..dimensions.y as u64 {
+        let start = y as usize * padded_width as usize;
+        v.extend_from_slice(&buffer_view[start..start + byte_width as usize]);
+    }
+    Ok(v)
+}
+
+/// Turns 4 bytes per pixel texture data into an RGBA image, swapping the channels of BGRA textures.
+#[cfg(feature = "image")]
+pub fn rgba_image(
+    format: wgpu::TextureFormat,
+    dimensions: mint::Vector2<u32>,
+    mut data: Vec<u8>,
+) -> image::RgbaImage {
+    if matches!(
+        format,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+    ) {
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+    image::RgbaImage::from_raw(dimensions.x, dimensions.y, data)
+        .expect("Downloaded texture data matches its dimensions")
+}
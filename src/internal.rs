@@ -1,12 +1,46 @@
-use std::rc::Rc;
+use std::{collections::HashMap, rc::Rc};
 
 use wgpu::util::DeviceExt;
 
-use crate::{Transform, Vertex};
+use crate::{BlendMode, Camera, Handle, Rect, Transform, Vertex};
+
+/// Per-instance data, the model matrix, the sub-rectangle of the texture in UV coordinates and the tint.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InstanceType {
+    pub model: [[f32; 4]; 4],
+    pub src: [f32; 4],
+    pub color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for InstanceType {}
+unsafe impl bytemuck::Zeroable for InstanceType {}
+
+impl InstanceType {
+    pub fn new(model: glam::Mat4, src: Rect, color: rgb::RGBA<f32>) -> Self {
+        InstanceType {
+            model: model.to_cols_array_2d(),
+            src: [src.position.x, src.position.y, src.size.x, src.size.y],
+            color: [color.r, color.g, color.b, color.a],
+        }
+    }
+}
 
-pub(crate) type InstanceType = [[f32; 4]; 4];
 pub(crate) const INSTANCE_SIZE: usize = std::mem::size_of::<InstanceType>();
 
+pub(crate) const WHITE: rgb::RGBA<f32> = rgb::RGBA {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+    a: 1.0,
+};
+
+/// Source rectangle covering the whole texture.
+pub(crate) const FULL_SRC: Rect = Rect {
+    position: mint::Point2 { x: 0.0, y: 0.0 },
+    size: mint::Vector2 { x: 1.0, y: 1.0 },
+};
+
 pub(crate) fn instance_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
     wgpu::VertexBufferLayout {
         array_stride: INSTANCE_SIZE as u64,
@@ -32,6 +66,16 @@ pub(crate) fn instance_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
                 shader_location: 8,
                 format: wgpu::VertexFormat::Float32x4,
             },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                shader_location: 9,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            wgpu::VertexAttribute {
+                offset: std::mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                shader_location: 10,
+                format: wgpu::VertexFormat::Float32x4,
+            },
         ],
     }
 }
@@ -76,34 +120,334 @@ pub(crate) struct PipelineData {
     pub object_dimensions: mint::Vector2<f32>,
 }
 
+/// How a draw interacts with the stencil buffer of its render group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(crate) enum StencilMode {
+    /// The render group has no stencil buffer.
+    #[default]
+    Disabled,
+    /// Only draw where the stencil value equals the reference.
+    Test,
+    /// Add one to the stencil value where it equals the reference, without drawing any color.
+    Increment,
+    /// Subtract one from the stencil value where it equals the reference, without drawing any color.
+    Decrement,
+}
+
+impl StencilMode {
+    pub fn depth_stencil_state(self) -> Option<wgpu::DepthStencilState> {
+        let pass_op = match self {
+            StencilMode::Disabled => return None,
+            StencilMode::Test => wgpu::StencilOperation::Keep,
+            StencilMode::Increment => wgpu::StencilOperation::IncrementClamp,
+            StencilMode::Decrement => wgpu::StencilOperation::DecrementClamp,
+        };
+        let face = wgpu::StencilFaceState {
+            compare: wgpu::CompareFunction::Equal,
+            fail_op: wgpu::StencilOperation::Keep,
+            depth_fail_op: wgpu::StencilOperation::Keep,
+            pass_op,
+        };
+        Some(wgpu::DepthStencilState {
+            format: STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: !0,
+                write_mask: !0,
+            },
+            bias: wgpu::DepthBiasState::default(),
+        })
+    }
+
+    pub fn color_writes(self) -> wgpu::ColorWrites {
+        match self {
+            StencilMode::Disabled | StencilMode::Test => wgpu::ColorWrites::ALL,
+            StencilMode::Increment | StencilMode::Decrement => wgpu::ColorWrites::empty(),
+        }
+    }
+}
+
+pub(crate) const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+/// Everything that requires a separate pipeline for the same shader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub(crate) struct PipelineVariant {
+    pub blend_mode: BlendMode,
+    pub stencil_mode: StencilMode,
+}
+
+/// A shader and the pipelines built from it, one for each variant it has been drawn with.
+pub(crate) struct ShaderPipelines {
+    pub name: String,
+    pub shader: wgpu::ShaderModule,
+    /// Fragment entry point returning premultiplied color, used by [`BlendMode::Multiply`].
+    pub premultiplied_entry_point: Option<&'static str>,
+    pub variants: HashMap<PipelineVariant, wgpu::RenderPipeline>,
+}
+
+pub(crate) const STANDARD_PIPELINE: Handle = Handle::nth(0);
+pub(crate) const TEXT_PIPELINE: Handle = Handle::nth(1);
+
 /// This is unique between .draw() calls
 pub(crate) struct DrawData {
-    pub pipeline_id: usize,
+    pub pipeline_id: Handle,
+    pub variant: PipelineVariant,
+    pub stencil_reference: u32,
+    pub scissor: Option<Rect>,
+    pub camera: Option<Camera>,
     pub transform: Transform,
+    /// Sub-rectangle of the texture to draw, in UV coordinates.
+    pub src: Rect,
+    pub size: ObjectSize,
     pub uniform_extra: Vec<u8>,
 }
 
+/// Size of a drawable in render units, resolved at submit time. See `SizeMode`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ObjectSize {
+    Unit,
+    Render,
+    Texels(mint::Vector2<f32>),
+}
+
+impl ObjectSize {
+    pub fn dimensions(
+        self,
+        render_dimensions: mint::Vector2<f32>,
+        target_dimensions: mint::Vector2<u32>,
+    ) -> mint::Vector2<f32> {
+        match self {
+            ObjectSize::Unit => mint::Vector2 { x: 1.0, y: 1.0 },
+            ObjectSize::Render => render_dimensions,
+            ObjectSize::Texels(texels) => mint::Vector2 {
+                x: texels.x * render_dimensions.x / target_dimensions.x as f32,
+                y: texels.y * render_dimensions.y / target_dimensions.y as f32,
+            },
+        }
+    }
+}
+
 pub(crate) struct RenderData {
     pub pipeline_data: PipelineData,
     pub instance_count: u32,
     pub instance_data: DrawData,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum TargetId {
-    Screen,
-    CanvasId(usize),
-}
+impl RenderData {
+    /// Model matrix of the draw with its size resolved for the render group.
+    pub fn model_matrix(
+        &self,
+        render_dimensions: mint::Vector2<f32>,
+        target_dimensions: mint::Vector2<u32>,
+    ) -> glam::Mat4 {
+        let size = self
+            .instance_data
+            .size
+            .dimensions(render_dimensions, target_dimensions);
+        let mut transform = self.instance_data.transform;
+        transform.scale.x *= size.x;
+        transform.scale.y *= size.y;
+        transform.as_matrix(self.pipeline_data.object_dimensions)
+    }
+
+    /// Whether this draws a single instance using the identity instance buffer.
+    pub fn is_single(&self, identity_instance_buffer: &Rc<wgpu::Buffer>) -> bool {
+        self.instance_count == 1
+            && Rc::ptr_eq(
+                &self.pipeline_data.instance_buffer,
+                identity_instance_buffer,
+            )
+    }
+
+    /// Whether the instance differs from the identity instance, so it has to be written to the frame instance buffer.
+    pub fn has_instance_data(&self) -> bool {
+        self.instance_data.src != FULL_SRC || self.instance_data.transform.color != WHITE
+    }
 
-impl Default for TargetId {
-    fn default() -> Self {
-        Self::Screen
+    /// Whether both draws only differ by their transform, source rectangle and color, so they can be drawn as instances of a single draw call.
+    pub fn can_batch_with(
+        &self,
+        other: &RenderData,
+        identity_instance_buffer: &Rc<wgpu::Buffer>,
+    ) -> bool {
+        self.is_single(identity_instance_buffer)
+            && other.is_single(identity_instance_buffer)
+            && Rc::ptr_eq(
+                &self.pipeline_data.mesh_buffer,
+                &other.pipeline_data.mesh_buffer,
+            )
+            && Rc::ptr_eq(
+                &self.pipeline_data.bind_group,
+                &other.pipeline_data.bind_group,
+            )
+            && self.instance_data.pipeline_id == other.instance_data.pipeline_id
+            && self.instance_data.variant == other.instance_data.variant
+            && self.instance_data.stencil_reference == other.instance_data.stencil_reference
+            && self.instance_data.scissor == other.instance_data.scissor
+            && self.instance_data.camera == other.instance_data.camera
+            && self.instance_data.uniform_extra == other.instance_data.uniform_extra
     }
 }
 
+/// Consecutive draws that are drawn with a single draw call.
+pub(crate) struct DrawBatch {
+    /// Indices into the render group's queue, in draw order.
+    pub draws: Vec<usize>,
+    /// Whether the instances are written to the frame instance buffer, this is needed to merge draws or to use a source rectangle or color.
+    pub uses_frame_instances: bool,
+    /// Where the instances start in the frame instance buffer.
+    pub instance_offset: u64,
+}
+
+pub(crate) struct CanvasTarget {
+    pub view: wgpu::TextureView,
+    pub dimensions: mint::Vector2<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum TargetId {
+    #[default]
+    Screen,
+    CanvasId(Handle),
+}
+
 #[derive(Default)]
 pub(crate) struct RenderGroup {
     pub target_id: TargetId,
     pub clear_color: Option<rgb::RGBA<f32, f32>>,
     pub queue: Vec<RenderData>,
+    /// Render dimensions used for this group instead of the context's, used by canvases.
+    pub projection: Option<mint::Vector2<f32>>,
+    /// Whether the render pass needs a stencil buffer, this is set once a mask is pushed.
+    pub uses_stencil: bool,
+    /// Active masks, these are needed to undo the mask when it's popped.
+    pub mask_stack: Vec<(PipelineData, Transform)>,
+}
+
+impl RenderGroup {
+    /// Indices into the queue, stable sorted by layer.
+    /// Draws are only reordered between mask changes so that masks keep applying to the same draws.
+    pub fn draw_order(&self) -> Vec<usize> {
+        let mut order = (0..self.queue.len()).collect::<Vec<_>>();
+        for run in order.split_mut(|&idx| {
+            matches!(
+                self.queue[idx].instance_data.variant.stencil_mode,
+                StencilMode::Increment | StencilMode::Decrement
+            )
+        }) {
+            run.sort_by_key(|&idx| self.queue[idx].instance_data.transform.layer);
+        }
+        order
+    }
+
+    /// Groups the queue, in draw order, into batches of draws that can be merged.
+    pub fn batches(&self, identity_instance_buffer: &Rc<wgpu::Buffer>) -> Vec<DrawBatch> {
+        let mut batches: Vec<DrawBatch> = Vec::new();
+        for idx in self.draw_order() {
+            match batches.last_mut() {
+                Some(batch)
+                    if self.queue[batch.draws[batch.draws.len() - 1]]
+                        .can_batch_with(&self.queue[idx], identity_instance_buffer) =>
+                {
+                    batch.draws.push(idx)
+                }
+                _ => batches.push(DrawBatch {
+                    draws: vec![idx],
+                    uses_frame_instances: false,
+                    instance_offset: 0,
+                }),
+            }
+        }
+        for batch in &mut batches {
+            let first = &self.queue[batch.draws[0]];
+            batch.uses_frame_instances = batch.draws.len() > 1
+                || (first.is_single(identity_instance_buffer) && first.has_instance_data());
+        }
+        batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GraphicsConfig, GraphicsContext, Image, Render};
+
+    /// Batches of a render where `second` tweaks the second of two draws of the same image.
+    fn batches(second: impl FnOnce(&mut RenderData, &Image)) -> Vec<(Vec<usize>, bool)> {
+        let ctx = GraphicsContext::new_headless(
+            [32, 32],
+            GraphicsConfig {
+                vsync: false,
+                render_dimensions: [1.0, 1.0].into(),
+            },
+        )
+        .unwrap_or_else(|e| panic!("Creating a headless context failed: {}", e));
+        let image = Image::new(&ctx, [1, 1], &[255; 4]).unwrap();
+        let other = Image::new(&ctx, [1, 1], &[255; 4]).unwrap();
+        let mut render = Render::new();
+        image.draw(&mut render, Transform::default());
+        image.draw(&mut render, Transform::default());
+        let group = &mut render.render_groups[0];
+        second(&mut group.queue[1], &other);
+        group
+            .batches(&ctx.identity_instance_buffer)
+            .into_iter()
+            .map(|batch| (batch.draws, batch.uses_frame_instances))
+            .collect()
+    }
+
+    #[test]
+    fn compatible_draws_are_merged() {
+        assert_eq!(batches(|_, _| {}), [(vec![0, 1], true)]);
+        assert_eq!(
+            batches(|data, _| {
+                data.instance_data.transform.position = [0.5, 0.5].into();
+                data.instance_data.transform.color = rgb::RGBA::new(1.0, 0.0, 0.0, 1.0);
+                data.instance_data.src = Rect::new(0.0, 0.0, 0.5, 0.5);
+            }),
+            [(vec![0, 1], true)]
+        );
+    }
+
+    #[test]
+    fn incompatible_draws_break_batches() {
+        let split = [(vec![0], false), (vec![1], false)];
+        assert_eq!(
+            batches(|data, other| data.pipeline_data.bind_group = Rc::clone(&other.data.bind_group)),
+            split
+        );
+        assert_eq!(
+            batches(|data, _| data.instance_data.scissor = Some(Rect::new(0.0, 0.0, 0.5, 0.5))),
+            split
+        );
+        assert_eq!(
+            batches(|data, _| data.instance_data.camera = Some(Camera::new([0.5, 0.5]))),
+            split
+        );
+        assert_eq!(
+            batches(|data, _| data.instance_data.uniform_extra = vec![1, 2, 3, 4]),
+            split
+        );
+        assert_eq!(
+            batches(|data, _| data.instance_data.stencil_reference = 1),
+            split
+        );
+    }
+
+    #[test]
+    fn single_draws_with_instance_data_use_frame_instances() {
+        let tinted = batches(|data, other| {
+            data.pipeline_data.bind_group = Rc::clone(&other.data.bind_group);
+            data.instance_data.transform.color = rgb::RGBA::new(1.0, 0.0, 0.0, 1.0);
+        });
+        assert_eq!(tinted, [(vec![0], false), (vec![1], true)]);
+        let cropped = batches(|data, other| {
+            data.pipeline_data.bind_group = Rc::clone(&other.data.bind_group);
+            data.instance_data.src = Rect::new(0.0, 0.0, 0.5, 0.5);
+        });
+        assert_eq!(cropped, [(vec![0], false), (vec![1], true)]);
+    }
 }
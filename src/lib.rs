@@ -1,14 +1,19 @@
 #![warn(clippy::clone_on_ref_ptr)]
 
-pub(crate) use crate::internal::*;
-pub use crate::{context::*, drawables::*, error::*, shader::*};
+pub use crate::{animation::*, camera::*, context::*, drawables::*, error::*, shader::*};
+pub(crate) use crate::{internal::*, store::*};
 
+mod animation;
+mod camera;
 mod context;
 mod drawables;
 mod error;
+#[cfg(feature = "golden")]
+pub mod golden;
 pub(crate) mod helpers;
 mod internal;
 mod shader;
+mod store;
 
 /// Vertex data.
 #[repr(C)]
@@ -178,6 +183,11 @@ pub struct Transform {
     pub rotation: Angle,
     /// Offset.
     pub offset: mint::Point2<f32>,
+    /// Draw order within a render group, higher layers are drawn on top.
+    /// Draws on the same layer are drawn in the order they were made.
+    pub layer: i32,
+    /// Tint multiplied with the drawn colors, the alpha can be used to fade objects.
+    pub color: rgb::RGBA<f32>,
 }
 
 impl Transform {
@@ -203,13 +213,222 @@ impl Default for Transform {
             rotation: Angle::from_radians(0.0),
             // TODO maybe this shouldn't default to 0.5..
             offset: [0.5, 0.5].into(),
+            layer: 0,
+            color: WHITE,
+        }
+    }
+}
+
+/// Axis aligned rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    /// Top left corner.
+    pub position: mint::Point2<f32>,
+    /// Width and height.
+    pub size: mint::Vector2<f32>,
+}
+
+impl Rect {
+    /// Creates a new rectangle from its top left corner and size.
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Self {
+        Rect {
+            position: [x, y].into(),
+            size: [w, h].into(),
+        }
+    }
+
+    /// Gets the overlapping area of two rectangles, this is empty if they don't overlap.
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.position.x.max(other.position.x);
+        let y = self.position.y.max(other.position.y);
+        let right = (self.position.x + self.size.x).min(other.position.x + other.size.x);
+        let bottom = (self.position.y + self.size.y).min(other.position.y + other.size.y);
+        Rect::new(x, y, (right - x).max(0.0), (bottom - y).max(0.0))
+    }
+}
+
+/// How big textured drawables such as `Image` are before their transform is applied.
+/// This is resolved when the render is submitted, so it follows later projection changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SizeMode {
+    /// A single render unit, so the transform scale is the size in render units.
+    Unit,
+    /// The whole render area of the render group.
+    #[default]
+    Render,
+    /// The size of the texture, with each texel covering one pixel of the render target.
+    Natural,
+}
+
+impl SizeMode {
+    pub(crate) fn object_size(self, texels: mint::Vector2<f32>) -> ObjectSize {
+        match self {
+            SizeMode::Unit => ObjectSize::Unit,
+            SizeMode::Render => ObjectSize::Render,
+            SizeMode::Natural => ObjectSize::Texels(texels),
+        }
+    }
+}
+
+/// Decides how textures are filtered and what happens outside of their edges.
+///
+/// Example usage:
+/// ```rust
+/// // Crisp pixel art that repeats when drawn with a larger source rectangle.
+/// let sampler = SamplerOptions::nearest().with_address_mode(wgpu::AddressMode::Repeat);
+/// image.set_sampler(ctx, sampler);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    /// Filter used when the texture is drawn larger than its size.
+    pub mag_filter: wgpu::FilterMode,
+    /// Filter used when the texture is drawn smaller than its size.
+    pub min_filter: wgpu::FilterMode,
+    /// Filter used between mipmap levels.
+    pub mipmap_filter: wgpu::FilterMode,
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    /// Maximum anisotropy, 1 disables anisotropic filtering.
+    /// Other values are rounded down to 2, 4, 8 or 16 and only used when all filters are linear.
+    pub anisotropy: u8,
+}
+
+impl SamplerOptions {
+    /// Nearest filtering everywhere, for pixel art.
+    pub fn nearest() -> Self {
+        SamplerOptions {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        }
+    }
+
+    /// Bilinear filtering, for smooth scaling of images without mipmaps.
+    pub fn linear() -> Self {
+        SamplerOptions {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        }
+    }
+
+    /// Trilinear filtering, which also blends between mip levels.
+    /// Use this with `ImageOptions::generate_mipmaps` for images that are drawn much smaller than their size.
+    pub fn trilinear() -> Self {
+        SamplerOptions {
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Self::linear()
+        }
+    }
+
+    /// Uses the same address mode for both axes.
+    pub fn with_address_mode(mut self, address_mode: wgpu::AddressMode) -> Self {
+        self.address_mode_u = address_mode;
+        self.address_mode_v = address_mode;
+        self
+    }
+
+    pub(crate) fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            label: Some("Oblivion_Sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: self.anisotropy_clamp(),
+            ..Default::default()
+        }
+    }
+
+    /// The anisotropy as a power of two wgpu accepts, wgpu panics on other values or when a filter is nearest.
+    fn anisotropy_clamp(&self) -> Option<std::num::NonZeroU8> {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|filter| *filter == wgpu::FilterMode::Linear);
+        if !linear || self.anisotropy < 2 {
+            return None;
+        }
+        let anisotropy = self.anisotropy.min(16);
+        std::num::NonZeroU8::new(1 << (u8::BITS - 1 - anisotropy.leading_zeros()))
+    }
+}
+
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        SamplerOptions {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            anisotropy: 1,
+        }
+    }
+}
+
+/// Decides how drawn colors are combined with what's already on the render target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Standard alpha blending.
+    #[default]
+    Alpha,
+    /// Adds the color on top, useful for lights and particles.
+    Additive,
+    /// Multiplies the color with what's below, darkening it. Transparent pixels leave what's below
+    /// untouched, partly transparent ones blend towards it.
+    Multiply,
+    /// Alpha blending for colors that are already multiplied by their alpha, such as canvases.
+    Premultiplied,
+    /// Overwrites what's below, ignoring alpha.
+    Replace,
+}
+
+impl BlendMode {
+    pub(crate) fn blend_state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            // Expects premultiplied color: `src * dst + dst * (1 - a)` lerps from `dst` to
+            // `src * dst` by alpha.
+            BlendMode::Multiply => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::DstAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            BlendMode::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
         }
     }
 }
 
 /// Stores a record and information about draw calls that can then be submitted to the context.
 pub struct Render {
-    shader_stack: Vec<usize>,
+    shader_stack: Vec<Handle>,
+    blend_mode_stack: Vec<BlendMode>,
+    scissor_stack: Vec<Rect>,
+    camera_stack: Vec<Camera>,
     render_groups: Vec<RenderGroup>,
     render_stack: Vec<usize>,
     // TODO make this a big buffer for all shader datas rather than just the active one?
@@ -221,6 +440,9 @@ impl Default for Render {
     fn default() -> Self {
         Self {
             shader_stack: Default::default(),
+            blend_mode_stack: Default::default(),
+            scissor_stack: Default::default(),
+            camera_stack: Default::default(),
             render_groups: vec![RenderGroup::default()],
             render_stack: Default::default(),
             active_shader_data: Vec::new(),
@@ -238,47 +460,110 @@ impl Render {
         &mut self.render_groups[self.render_stack.last().copied().unwrap_or(0)]
     }
 
+    /// Records a draw with the active render state, returning it so drawables can set a source rectangle or size.
     pub(crate) fn push_data(
         &mut self,
         pipeline_data: PipelineData,
         instance_count: u32,
         transform: Transform,
-        default_pipeline_id: usize,
-    ) {
+        default_pipeline_id: Handle,
+    ) -> &mut DrawData {
         let pipeline_id = self
             .shader_stack
             .last()
             .copied()
             .unwrap_or(default_pipeline_id);
+        let blend_mode = self.blend_mode_stack.last().copied().unwrap_or_default();
+        let group = self.current_render_group();
+        let stencil_mode = if group.uses_stencil {
+            StencilMode::Test
+        } else {
+            StencilMode::Disabled
+        };
+        let stencil_reference = group.mask_stack.len() as u32;
+        self.push_draw(
+            pipeline_data,
+            instance_count,
+            transform,
+            pipeline_id,
+            PipelineVariant {
+                blend_mode,
+                stencil_mode,
+            },
+            stencil_reference,
+        )
+    }
+
+    /// Records a draw, returning it so callers can adjust it.
+    fn push_draw(
+        &mut self,
+        pipeline_data: PipelineData,
+        instance_count: u32,
+        transform: Transform,
+        pipeline_id: Handle,
+        variant: PipelineVariant,
+        stencil_reference: u32,
+    ) -> &mut DrawData {
+        let scissor = self.scissor_stack.last().copied();
+        let camera = self.camera_stack.last().copied();
         let uniform_extra = self.active_shader_data.clone();
         self.current_render_group().queue.push(RenderData {
             pipeline_data,
             instance_count,
             instance_data: DrawData {
                 pipeline_id,
+                variant,
+                stencil_reference,
+                scissor,
+                camera,
                 transform,
+                src: FULL_SRC,
+                size: ObjectSize::Unit,
                 uniform_extra,
             },
-        })
+        });
+        &mut self
+            .current_render_group()
+            .queue
+            .last_mut()
+            .expect("A draw was just pushed")
+            .instance_data
     }
 
     pub fn reset(&mut self) {
         self.shader_stack.clear();
+        self.blend_mode_stack.clear();
+        self.scissor_stack.clear();
+        self.camera_stack.clear();
         self.render_groups.truncate(1);
         self.render_groups[0].queue.clear();
         self.render_groups[0].clear_color = None;
         self.render_groups[0].target_id = TargetId::Screen;
+        self.render_groups[0].uses_stencil = false;
+        self.render_groups[0].mask_stack.clear();
         self.render_stack.clear();
         self.active_shader_data.clear();
     }
 }
 
-/// Clears the screen with a color.
+/// Clears the screen with a color. Active masks stay in effect for the draws after it.
 pub fn clear(render: &mut Render, color: impl Into<rgb::RGBA<f32, f32>>) {
-    render.current_render_group().clear_color = Some(color.into());
+    let group = render.current_render_group();
+    group.clear_color = Some(color.into());
     // We also need to clear the draw queue to give the illusion of the clear color overwriting everything else
     // This should produce the same behavior as just overwriting everything else though.
-    render.current_render_group().queue.clear();
+    group.queue.clear();
+    // The mask draws were part of the queue, the active masks have to be drawn again to stay in effect.
+    let masks = group.mask_stack.clone();
+    for (stencil_reference, (pipeline_data, transform)) in masks.into_iter().enumerate() {
+        push_mask_draw(
+            render,
+            pipeline_data,
+            transform,
+            StencilMode::Increment,
+            stencil_reference as u32,
+        );
+    }
 }
 
 /// Sets an active shader. Use `oblivion::pop_shader` to unset it.
@@ -291,14 +576,122 @@ pub fn pop_shader(render: &mut Render) {
     render.shader_stack.pop();
 }
 
+/// Sets an active blend mode. Use `oblivion::pop_blend_mode` to unset it.
+pub fn push_blend_mode(render: &mut Render, blend_mode: BlendMode) {
+    render.blend_mode_stack.push(blend_mode);
+}
+
+/// Removes the active blend mode and goes back to the previous one.
+pub fn pop_blend_mode(render: &mut Render) {
+    render.blend_mode_stack.pop();
+}
+
+/// Sets an active clip rectangle in render units, nothing outside of it will be drawn.
+/// Nested clip rectangles are intersected with the current one. Use `oblivion::pop_scissor` to unset it.
+pub fn push_scissor(render: &mut Render, rect: Rect) {
+    let rect = match render.scissor_stack.last() {
+        Some(current) => current.intersect(&rect),
+        None => rect,
+    };
+    render.scissor_stack.push(rect);
+}
+
+/// Removes the active clip rectangle and goes back to the previous one.
+pub fn pop_scissor(render: &mut Render) {
+    render.scissor_stack.pop();
+}
+
+/// Sets an active camera. Use `oblivion::pop_camera` to unset it.
+pub fn push_camera(render: &mut Render, camera: Camera) {
+    render.camera_stack.push(camera);
+}
+
+/// Removes the active camera and goes back to the previous one.
+pub fn pop_camera(render: &mut Render) {
+    render.camera_stack.pop();
+}
+
+/// Sets an active mask, later draws will only land where the mesh was drawn.
+/// Nested masks are intersected with the current one. Use `oblivion::pop_mask` to unset it.
+pub fn push_mask(render: &mut Render, mesh: &Mesh, transform: Transform) {
+    let group = render.current_render_group();
+    if !group.uses_stencil {
+        // Every pipeline in a render pass with a stencil buffer has to be stencil aware.
+        group.uses_stencil = true;
+        for data in &mut group.queue {
+            data.instance_data.variant.stencil_mode = StencilMode::Test;
+        }
+    }
+    let stencil_reference = group.mask_stack.len() as u32;
+    group.mask_stack.push((mesh.data.clone(), transform));
+    push_mask_draw(
+        render,
+        mesh.data.clone(),
+        transform,
+        StencilMode::Increment,
+        stencil_reference,
+    );
+}
+
+/// Removes the active mask and goes back to the previous one.
+pub fn pop_mask(render: &mut Render) {
+    let group = render.current_render_group();
+    if let Some((pipeline_data, transform)) = group.mask_stack.pop() {
+        // Undo the mask by decrementing the stencil values it incremented.
+        let stencil_reference = group.mask_stack.len() as u32 + 1;
+        push_mask_draw(
+            render,
+            pipeline_data,
+            transform,
+            StencilMode::Decrement,
+            stencil_reference,
+        );
+    }
+}
+
+fn push_mask_draw(
+    render: &mut Render,
+    pipeline_data: PipelineData,
+    transform: Transform,
+    stencil_mode: StencilMode,
+    stencil_reference: u32,
+) {
+    render.push_draw(
+        pipeline_data,
+        1,
+        transform,
+        STANDARD_PIPELINE,
+        PipelineVariant {
+            stencil_mode,
+            ..Default::default()
+        },
+        stencil_reference,
+    );
+}
+
 pub fn set_shader_data<T: bytemuck::Pod>(render: &mut Render, data: &T) {
     render.active_shader_data = bytemuck::bytes_of(data).to_vec();
 }
 
 /// Sets an active canvas. Use `oblivion::pop_canvas` to unset it.
+/// Draws to the canvas use its dimensions as projection, so positions are in canvas pixels.
 pub fn push_canvas(render: &mut Render, canvas: &Canvas) {
+    let dimensions = mint::Vector2 {
+        x: canvas.dimensions.x as f32,
+        y: canvas.dimensions.y as f32,
+    };
+    push_canvas_with_projection(render, canvas, dimensions);
+}
+
+/// Sets an active canvas with a custom projection. Use `oblivion::pop_canvas` to unset it.
+pub fn push_canvas_with_projection(
+    render: &mut Render,
+    canvas: &Canvas,
+    dimensions: impl Into<mint::Vector2<f32>>,
+) {
     render.render_groups.push(RenderGroup {
         target_id: TargetId::CanvasId(canvas.canvas_id),
+        projection: Some(dimensions.into()),
         ..Default::default()
     });
     render.render_stack.push(render.render_groups.len() - 1);
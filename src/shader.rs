@@ -1,23 +1,36 @@
-use crate::{helpers::create_pipeline, GraphicsContext};
+use std::rc::Rc;
+
+use crate::{
+    helpers::create_shader_pipelines, GraphicsContext, Handle, PipelineVariant, StoreGuard,
+    StoreHandle,
+};
 
 /// Shaders are used to change how objects are drawn.
+///
+/// The shader's pipelines are freed once the last clone is dropped.
+#[derive(Clone)]
 pub struct Shader {
-    pub(crate) pipeline_id: usize,
+    pub(crate) pipeline_id: Handle,
+    _guard: Rc<StoreGuard>,
 }
 
 impl Shader {
     /// Creates a new shader.
+    ///
+    /// The fragment entry point is `fs_main`. When drawn with [`BlendMode::Multiply`](crate::BlendMode::Multiply) it has to
+    /// return its color already multiplied by alpha.
     pub fn new(ctx: &mut GraphicsContext, source: wgpu::ShaderSource) -> Self {
-        ctx.pipeline_store.push(create_pipeline(
+        ctx.release_resources();
+        let pipeline_id = ctx.pipeline_store.insert(create_shader_pipelines(
             "CustomShader",
             &ctx.device,
-            ctx.surface_config.format,
             source,
-            &ctx.texture_bind_group_layout,
-            &ctx.mvp_bind_group_layout,
+            None,
         ));
+        ctx.ensure_pipeline(pipeline_id, PipelineVariant::default());
         Shader {
-            pipeline_id: ctx.pipeline_store.len() - 1,
+            pipeline_id,
+            _guard: StoreGuard::new(StoreHandle::Pipeline(pipeline_id), &ctx.release_queue),
         }
     }
 }
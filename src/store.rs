@@ -0,0 +1,130 @@
+use std::{cell::RefCell, rc::Rc};
+
+/// Refers to a value in a `Store`. Handles to removed values are detected using the generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Handle {
+    index: u32,
+    generation: u32,
+}
+
+impl Handle {
+    /// Handle of the n-th value inserted into a new store, used for the built-in pipelines.
+    pub const fn nth(index: u32) -> Self {
+        Handle {
+            index,
+            generation: 0,
+        }
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Slot allocator that reuses the slots of removed values.
+pub(crate) struct Store<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Store<T> {
+    fn default() -> Self {
+        Store {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Store<T> {
+    pub fn insert(&mut self, value: T) -> Handle {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                Handle {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                Handle {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<T> {
+        let slot = self.slots.get_mut(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle).is_some()
+    }
+}
+
+impl<T> std::ops::Index<Handle> for Store<T> {
+    type Output = T;
+
+    fn index(&self, handle: Handle) -> &Self::Output {
+        self.get(handle).expect("Stale handle")
+    }
+}
+
+/// Resources owned by the context's stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StoreHandle {
+    Canvas(Handle),
+    Pipeline(Handle),
+}
+
+pub(crate) type ReleaseQueue = Rc<RefCell<Vec<StoreHandle>>>;
+
+/// Queues the resource to be removed from its store once the last clone of its owner is dropped.
+pub(crate) struct StoreGuard {
+    handle: StoreHandle,
+    release_queue: ReleaseQueue,
+}
+
+impl StoreGuard {
+    pub fn new(handle: StoreHandle, release_queue: &ReleaseQueue) -> Rc<Self> {
+        Rc::new(StoreGuard {
+            handle,
+            release_queue: Rc::clone(release_queue),
+        })
+    }
+}
+
+impl Drop for StoreGuard {
+    fn drop(&mut self) {
+        self.release_queue.borrow_mut().push(self.handle);
+    }
+}
@@ -0,0 +1,74 @@
+use oblivion::{Animation, LoopMode};
+#[cfg(feature = "json")]
+use oblivion::{OblivionError, Rect, SpriteSheetLayout};
+
+#[test]
+fn once_stops_on_last_frame() {
+    let animation = Animation::uniform(0..3, 0.1, LoopMode::Once);
+    assert_eq!(animation.frame_at(0.0), Some(0));
+    assert_eq!(animation.frame_at(0.15), Some(1));
+    assert_eq!(animation.frame_at(0.25), Some(2));
+    assert_eq!(animation.frame_at(10.0), Some(2));
+    assert!(!animation.is_finished(0.25));
+    assert!(animation.is_finished(0.3));
+}
+
+#[test]
+fn loop_wraps_around() {
+    let animation = Animation::new(LoopMode::Loop)
+        .with_frame(4, 0.1)
+        .with_frame(5, 0.3);
+    assert_eq!(animation.frame_at(0.05), Some(4));
+    assert_eq!(animation.frame_at(0.2), Some(5));
+    assert_eq!(animation.frame_at(0.45), Some(4));
+    assert!(!animation.is_finished(10.0));
+}
+
+#[test]
+fn ping_pong_doesnt_repeat_ends() {
+    let animation = Animation::uniform(0..4, 1.0, LoopMode::PingPong);
+    let frames = (0..8)
+        .map(|second| animation.frame_at(second as f32 + 0.5).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(frames, [0, 1, 2, 3, 2, 1, 0, 1]);
+}
+
+#[test]
+fn empty_animation_has_no_frame() {
+    assert_eq!(Animation::new(LoopMode::Loop).frame_at(1.0), None);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn sprite_sheet_from_aseprite_json() {
+    let json = r#"{
+        "frames": {
+            "tree 10.aseprite": { "frame": { "x": 128, "y": 128, "w": 128, "h": 128 }, "duration": 200 },
+            "tree 2.aseprite": { "frame": { "x": 0, "y": 0, "w": 128, "h": 128 }, "duration": 100 }
+        },
+        "meta": {
+            "frameTags": [ { "name": "idle", "from": 0, "to": 1, "direction": "reverse" } ]
+        }
+    }"#;
+    let layout = SpriteSheetLayout::from_json(json).unwrap();
+    assert_eq!(layout.frames[0], Rect::new(128.0, 128.0, 128.0, 128.0));
+    let idle = &layout.animations["idle"];
+    assert_eq!(idle.frames[0].index, 1);
+    assert_eq!(idle.frames[0].duration, 0.1);
+    assert_eq!(idle.mode, LoopMode::Loop);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn sprite_sheet_tag_outside_frames() {
+    for frames in ["[]", r#"[{ "frame": { "x": 0, "y": 0, "w": 8, "h": 8 } }]"#] {
+        let json = format!(
+            r#"{{ "frames": {}, "meta": {{ "frameTags": [ {{ "name": "walk", "from": 0, "to": 1 }} ] }} }}"#,
+            frames
+        );
+        assert!(matches!(
+            SpriteSheetLayout::from_json(&json),
+            Err(OblivionError::InvalidSpriteSheetTag { to: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use image::GenericImageView;
+use oblivion::{GraphicsConfig, GraphicsContext, Image, ImageOptions};
+
+pub const DIMENSIONS: [u32; 2] = [320, 180];
+
+/// Creates a headless context, a software adapter such as llvmpipe or lavapipe is enough.
+///
+/// Panics when no adapter is available, so a missing GPU fails the test instead of passing it.
+pub fn headless_context() -> GraphicsContext {
+    GraphicsContext::new_headless(
+        DIMENSIONS,
+        GraphicsConfig {
+            vsync: false,
+            render_dimensions: [1.0, 1.0].into(),
+        },
+    )
+    .unwrap_or_else(|e| panic!("Creating a headless context failed: {}", e))
+}
+
+pub fn happy_tree(ctx: &GraphicsContext) -> Image {
+    happy_tree_with_options(ctx, ImageOptions::default())
+}
+
+pub fn happy_tree_with_options(ctx: &GraphicsContext, options: ImageOptions) -> Image {
+    let image_bytes = include_bytes!("../../resources/textures/happy-tree.png");
+    let image_data = image::load_from_memory(image_bytes).unwrap();
+    let image_rgba = image_data.as_rgba8().unwrap();
+    let dimensions = image_data.dimensions();
+    Image::with_options(ctx, [dimensions.0, dimensions.1], image_rgba, options).unwrap()
+}
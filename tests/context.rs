@@ -0,0 +1,44 @@
+mod common;
+
+use oblivion::{Camera, MeshBuilder, Render, Transform};
+
+#[test]
+fn resize_headless() {
+    let mut ctx = common::headless_context();
+    let left_half = MeshBuilder::new()
+        .quad(
+            [0.0, 0.0],
+            [0.5, 1.0],
+            [1.0, 0.0, 0.0, 1.0],
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .build(&ctx);
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.0, 0.0, 1.0, 1.0]);
+    left_half.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    ctx.submit_render(&render).unwrap();
+
+    ctx.resize([200, 100]);
+    assert_eq!(ctx.surface_dimensions(), [200, 100].into());
+    // The render dimensions now span the new surface.
+    let corner = Camera::new([0.5, 0.5]).world_to_screen(&ctx, [1.0, 1.0]);
+    assert_eq!((corner.x, corner.y), (200.0, 100.0));
+
+    ctx.submit_render(&render).unwrap();
+    let rgba = ctx.download_screen_rgba().unwrap();
+    assert_eq!(rgba.len(), 200 * 100 * 4);
+    let pixel = |x: usize, y: usize| &rgba[(y * 200 + x) * 4..][..4];
+    assert_eq!(pixel(98, 50), [255, 0, 0, 255]);
+    assert_eq!(pixel(101, 50), [0, 0, 255, 255]);
+
+    // Minimized windows report a size of zero, which is ignored.
+    ctx.resize([0, 0]);
+    assert_eq!(ctx.surface_dimensions(), [200, 100].into());
+}
@@ -0,0 +1,965 @@
+//! Golden image tests mirroring the examples.
+//! Run with `cargo test --features golden`, set `OBLIVION_BLESS=1` to update the references.
+
+use image::GenericImageView;
+use oblivion::{
+    Angle, Animation, AnimationPlayer, BatchInstance, BlendMode, Camera, Canvas, GraphicsContext,
+    Image, ImageBatch, ImageOptions, LoopMode, Mesh, MeshBuilder, PixelFormat, Rect, Render,
+    SamplerOptions, SpriteSheet, Text, TextFragment, TextureAtlasBuilder, Transform,
+};
+
+mod common;
+
+use common::{happy_tree, happy_tree_with_options, DIMENSIONS};
+
+const TOLERANCE: u8 = 8;
+
+fn check(ctx: &mut GraphicsContext, render: &Render, name: &str) {
+    let reference = format!("{}/tests/golden/{}.png", env!("CARGO_MANIFEST_DIR"), name);
+    if let Err(e) = oblivion::golden::compare_golden(ctx, render, reference, TOLERANCE) {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn compare_rgba_tolerance() {
+    let expected = [10, 20, 30, 255, 100, 100, 100, 255];
+    let actual = [12, 18, 30, 255, 100, 100, 120, 255];
+    let comparison = oblivion::golden::compare_rgba(&actual, &expected, 2);
+    assert_eq!(comparison.mismatched, 1);
+    assert_eq!(&comparison.diff[4..], &[255, 0, 0, 255]);
+    assert_eq!(
+        oblivion::golden::compare_rgba(&actual, &expected, 20).mismatched,
+        0
+    );
+}
+
+#[test]
+fn draw_image() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_image");
+}
+
+#[test]
+fn draw_transform() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    let elapsed = Angle::from_radians(1.0);
+    for i in 0..12 {
+        image.draw(
+            &mut render,
+            Transform {
+                scale: [
+                    ((elapsed * 2.0).cos() * 0.5 + 1.0) / 4.0,
+                    ((elapsed * 2.0).sin() * 0.5 + 1.0) / 4.0,
+                ]
+                .into(),
+                position: [
+                    ((elapsed + i as f32 * std::f32::consts::FRAC_PI_6).sin() * 0.5 + 1.0) / 2.0,
+                    ((elapsed + i as f32 * std::f32::consts::FRAC_PI_6).cos() * 0.5 + 1.0) / 2.0,
+                ]
+                .into(),
+                rotation: Angle::from_radians(elapsed.sin()),
+                ..Default::default()
+            },
+        );
+    }
+    check(&mut ctx, &render, "draw_transform");
+}
+
+#[test]
+fn draw_mesh() {
+    let mut ctx = common::headless_context();
+    let mesh = MeshBuilder::new()
+        .quad(
+            [0.0, 0.0],
+            [1.0 / 3.0, 1.0 / 3.0],
+            [0.0, 0.0, 1.0, 1.0],
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .quad(
+            [1.0 / 3.0, 1.0 / 3.0],
+            [1.0 / 3.0, 1.0 / 3.0],
+            [0.0, 1.0, 0.0, 1.0],
+            oblivion::DrawMode::stroke(0.02),
+        )
+        .unwrap()
+        .quad(
+            [2.0 / 3.0, 2.0 / 3.0],
+            [1.0 / 3.0, 1.0 / 3.0],
+            [1.0, 0.0, 0.0, 1.0],
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .tri(
+            [0.0, 2.0 / 3.0],
+            [1.0 / 3.0, 1.0 / 3.0],
+            [1.0, 1.0, 0.0, 1.0],
+            oblivion::DrawMode::stroke(0.02),
+        )
+        .unwrap()
+        .circle(
+            [2.0 / 3.0, 0.0],
+            [1.0 / 3.0, 1.0 / 3.0],
+            [1.0, 1.0, 1.0, 1.0],
+            0.01,
+            oblivion::DrawMode::stroke(0.02),
+        )
+        .unwrap()
+        .build(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    mesh.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_mesh");
+}
+
+#[test]
+fn draw_text() {
+    let mut ctx = common::headless_context();
+    let mut text = Text::new(&mut ctx);
+    text.add_text(["Hello ", "World\n"]);
+    text.add_text([TextFragment {
+        text: "Foobar".to_owned(),
+        font: None,
+        color: [1.0, 0.5, 0.25, 1.0].into(),
+        scale: 24.0,
+    }]);
+    text.flush(&mut ctx);
+    let mut counter = Text::new(&mut ctx);
+    counter.add_text(["Frame Count: 1"]);
+    counter.flush(&mut ctx);
+    let (position, size) = counter.bounds();
+    let background = MeshBuilder::new()
+        .quad(
+            position,
+            size,
+            [1.0, 0.0, 1.0, 0.5],
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .build(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    text.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    counter.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    background.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_text");
+}
+
+#[test]
+fn draw_canvas() {
+    let mut ctx = common::headless_context();
+    let canvas_hidden = Canvas::new(&mut ctx, [64, 64]);
+    let canvas_shown = Canvas::new(&mut ctx, [64, 64]);
+    let mut text_hidden = Text::new(&mut ctx);
+    text_hidden.add_text(["Hidden"]);
+    text_hidden.flush(&mut ctx);
+    let mut text_shown = Text::new(&mut ctx);
+    text_shown.add_text(["Shown"]);
+    text_shown.flush(&mut ctx);
+
+    // Canvases are in pixels, text keeps its pixel size in them.
+    let centered = Transform {
+        position: [32.0, 32.0].into(),
+        scale: [0.4, 0.4].into(),
+        ..Default::default()
+    };
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    oblivion::push_canvas(&mut render, &canvas_hidden);
+    text_hidden.draw(&mut render, centered);
+    oblivion::pop_canvas(&mut render);
+
+    oblivion::push_canvas(&mut render, &canvas_shown);
+    oblivion::clear(&mut render, [1.0, 0.2, 0.3, 1.0]);
+    text_shown.draw(&mut render, centered);
+    oblivion::pop_canvas(&mut render);
+    for x in [0.33, 0.66] {
+        canvas_shown.draw(
+            &mut render,
+            Transform {
+                position: [x, 0.5].into(),
+                scale: [0.25, 0.25].into(),
+                ..Default::default()
+            },
+        );
+    }
+    // The screen renders before the canvases, so like the example it shows the previous frame's canvases.
+    ctx.submit_render(&render).unwrap();
+    check(&mut ctx, &render, "draw_canvas");
+}
+
+#[test]
+fn draw_image_batch() {
+    let mut ctx = common::headless_context();
+    let image_bytes = include_bytes!("../resources/textures/happy-tree.png");
+    let image_data = image::load_from_memory(image_bytes).unwrap();
+    let image_rgba = image_data.as_rgba8().unwrap();
+    let dimensions = image_data.dimensions();
+    let mut batch = ImageBatch::new(&ctx, [dimensions.0, dimensions.1], image_rgba).unwrap();
+    batch.add_instance(
+        &mut ctx,
+        &[
+            Transform {
+                position: [0.25, 0.25].into(),
+                scale: [0.25, 0.5].into(),
+                ..Default::default()
+            },
+            Transform {
+                position: [0.75, 0.75].into(),
+                scale: [0.5, 0.25].into(),
+                ..Default::default()
+            },
+        ],
+    );
+    batch.add_instance(
+        &mut ctx,
+        &[Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        }],
+    );
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    batch.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_image_batch");
+}
+
+#[test]
+fn draw_projection() {
+    let mut ctx = common::headless_context();
+    let dimensions = mint::Vector2 {
+        x: DIMENSIONS[0] as f32,
+        y: DIMENSIONS[1] as f32,
+    };
+    ctx.set_projection(dimensions);
+    let mesh: Mesh = MeshBuilder::new()
+        .circle(
+            [dimensions.x / 2.0, dimensions.y / 2.0],
+            [100.0, 100.0],
+            [1.0, 0.0, 0.0, 1.0],
+            0.01,
+            oblivion::DrawMode::stroke(dimensions.x / 100.0),
+        )
+        .unwrap()
+        .build(&ctx);
+    let mut text = Text::new(&mut ctx);
+    text.add_text(["Projection Test!"]);
+    text.flush(&mut ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    mesh.draw(&mut render, Transform::default());
+    text.draw(
+        &mut render,
+        Transform {
+            position: [dimensions.x / 2.0, dimensions.y / 6.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_projection");
+}
+
+#[test]
+fn draw_blend_modes() {
+    let mut ctx = common::headless_context();
+    let mesh = MeshBuilder::new()
+        .circle(
+            [0.0, 0.0],
+            [0.3, 0.3],
+            [0.8, 0.4, 0.2, 0.5],
+            0.01,
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .build(&ctx);
+    // A square with a transparent white border and a half transparent white half. The border must
+    // only show up with Premultiplied and Replace, the white half must vanish with Multiply.
+    let bordered = (0..8 * 8)
+        .flat_map(|idx| match (idx % 8, idx / 8) {
+            (2..=3, 2..=5) => [255, 255, 255, 128],
+            (4..=5, 2..=5) => [128, 255, 128, 255],
+            _ => [255, 255, 255, 0],
+        })
+        .collect::<Vec<u8>>();
+    let bordered = Image::with_options(
+        &ctx,
+        [8, 8],
+        &bordered,
+        ImageOptions {
+            sampler: SamplerOptions::nearest(),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (i, blend_mode) in [
+        BlendMode::Alpha,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+        BlendMode::Premultiplied,
+        BlendMode::Replace,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        oblivion::push_blend_mode(&mut render, blend_mode);
+        for offset in [0.0, 0.05] {
+            mesh.draw(
+                &mut render,
+                Transform {
+                    position: [0.1 + i as f32 * 0.2, 0.5 + offset].into(),
+                    ..Default::default()
+                },
+            );
+        }
+        bordered.draw(
+            &mut render,
+            Transform {
+                position: [0.1 + i as f32 * 0.2, 0.8].into(),
+                scale: [0.15, 0.25].into(),
+                ..Default::default()
+            },
+        );
+        oblivion::pop_blend_mode(&mut render);
+    }
+    check(&mut ctx, &render, "draw_blend_modes");
+}
+
+#[test]
+fn draw_scissor() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    oblivion::push_scissor(&mut render, Rect::new(0.25, 0.0, 0.5, 1.0));
+    oblivion::push_scissor(&mut render, Rect::new(0.0, 0.25, 1.0, 0.5));
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    oblivion::pop_scissor(&mut render);
+    oblivion::pop_scissor(&mut render);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_scissor");
+}
+
+#[test]
+fn draw_mask() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+    let circle = MeshBuilder::new()
+        .circle(
+            [0.0, 0.0],
+            [0.25, 0.25],
+            [1.0, 1.0, 1.0, 1.0],
+            0.01,
+            oblivion::DrawMode::fill(),
+        )
+        .unwrap()
+        .build(&ctx);
+    let full = Transform {
+        position: [0.5, 0.5].into(),
+        ..Default::default()
+    };
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    oblivion::push_mask(&mut render, &circle, full);
+    oblivion::push_mask(
+        &mut render,
+        &circle,
+        Transform {
+            position: [0.6, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    image.draw(&mut render, full);
+    oblivion::pop_mask(&mut render);
+    oblivion::pop_mask(&mut render);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.125, 0.125].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_mask");
+}
+
+#[test]
+fn draw_layers() {
+    let mut ctx = common::headless_context();
+    let square = |color: [f32; 4]| {
+        MeshBuilder::new()
+            .quad([0.0, 0.0], [0.4, 0.4], color, oblivion::DrawMode::fill())
+            .unwrap()
+            .build(&ctx)
+    };
+    let red = square([1.0, 0.0, 0.0, 1.0]);
+    let green = square([0.0, 1.0, 0.0, 1.0]);
+    let blue = square([0.0, 0.0, 1.0, 1.0]);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (mesh, position, layer) in [
+        (&red, [0.4, 0.4], 2),
+        (&green, [0.5, 0.5], 1),
+        (&blue, [0.6, 0.6], 1),
+    ] {
+        mesh.draw(
+            &mut render,
+            Transform {
+                position: position.into(),
+                layer,
+                ..Default::default()
+            },
+        );
+    }
+    check(&mut ctx, &render, "draw_layers");
+}
+
+#[test]
+fn draw_camera() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+    let camera = Camera {
+        position: [0.25, 0.25].into(),
+        zoom: 2.0,
+        rotation: Angle::from_degrees(30.0),
+    };
+    let close =
+        |a: mint::Point2<f32>, b: [f32; 2]| (a.x - b[0]).abs() < 1e-3 && (a.y - b[1]).abs() < 1e-3;
+    let world = camera.screen_to_world(&ctx, [40.0, 100.0]);
+    assert!(close(camera.world_to_screen(&ctx, world), [40.0, 100.0]));
+    // The camera position is shown at the center of the surface.
+    assert!(close(
+        camera.world_to_screen(&ctx, camera.position),
+        [160.0, 90.0]
+    ));
+    assert!(close(
+        camera.screen_to_world(&ctx, [160.0, 90.0]),
+        [0.25, 0.25]
+    ));
+    // Without rotation a world unit spans the surface, multiplied by the zoom.
+    let unrotated = Camera {
+        rotation: Angle::from_degrees(0.0),
+        ..camera
+    };
+    assert!(close(
+        unrotated.world_to_screen(&ctx, [0.35, 0.3]),
+        [160.0 + 0.1 * 2.0 * 320.0, 90.0 + 0.05 * 2.0 * 180.0]
+    ));
+    let distance = |camera: &Camera| {
+        let a = camera.world_to_screen(&ctx, [0.0, 0.0]);
+        let b = camera.world_to_screen(&ctx, [0.1, 0.2]);
+        (b.x - a.x).hypot(b.y - a.y)
+    };
+    let zoomed_out = Camera {
+        zoom: 1.0,
+        ..camera
+    };
+    assert!((distance(&camera) - 2.0 * distance(&zoomed_out)).abs() < 1e-3);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    oblivion::push_camera(&mut render, camera);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.25, 0.25].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    oblivion::pop_camera(&mut render);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.875, 0.125].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_camera");
+}
+
+#[test]
+fn draw_canvas_pixel_projection() {
+    let mut ctx = common::headless_context();
+    let canvas = Canvas::new(&mut ctx, [64, 64]);
+    let mesh = MeshBuilder::new()
+        .quad(
+            [8.0, 8.0],
+            [48.0, 48.0],
+            [0.0, 1.0, 0.0, 1.0],
+            oblivion::DrawMode::stroke(4.0),
+        )
+        .unwrap()
+        .build(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    oblivion::push_canvas(&mut render, &canvas);
+    oblivion::clear(&mut render, [1.0, 1.0, 1.0, 1.0]);
+    mesh.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    oblivion::pop_canvas(&mut render);
+
+    let mut canvas_render = Render::new();
+    oblivion::clear(&mut canvas_render, [0.1, 0.2, 0.3, 1.0]);
+    canvas.draw(
+        &mut canvas_render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    ctx.submit_render(&render).unwrap();
+    check(&mut ctx, &canvas_render, "draw_canvas_pixel_projection");
+}
+
+#[test]
+fn draw_atlas() {
+    let mut ctx = common::headless_context();
+    let solid = |dimensions: [u32; 2], color: [u8; 4]| {
+        color.repeat((dimensions[0] * dimensions[1]) as usize)
+    };
+    let mut builder = TextureAtlasBuilder::new();
+    builder
+        .padding(2)
+        .extrude(true)
+        .add("red", [8, 8], &solid([8, 8], [255, 0, 0, 255]))
+        .unwrap()
+        .add("green", [16, 8], &solid([16, 8], [0, 255, 0, 255]))
+        .unwrap()
+        .add("blue", [8, 16], &solid([8, 16], [0, 0, 255, 255]))
+        .unwrap();
+    let atlas = builder.build(&ctx).unwrap();
+    assert_eq!(atlas.pages().len(), 1);
+    assert_eq!(atlas.get(&"green").unwrap().dimensions(), [16, 8].into());
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (idx, key) in ["red", "green", "blue"].iter().enumerate() {
+        atlas.get(key).unwrap().draw(
+            &mut render,
+            Transform {
+                position: [0.25 + idx as f32 * 0.25, 0.5].into(),
+                scale: [0.2, 0.2].into(),
+                ..Default::default()
+            },
+        );
+    }
+    check(&mut ctx, &render, "draw_atlas");
+}
+
+#[test]
+fn draw_image_region() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    // Left and right halves of the image, swapped.
+    image.draw_region(
+        &mut render,
+        Rect::new(0.0, 0.0, 128.0, 256.0),
+        Transform {
+            position: [0.75, 0.5].into(),
+            scale: [0.25, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    image.draw_region(
+        &mut render,
+        Rect::new(128.0, 0.0, 128.0, 256.0),
+        Transform {
+            position: [0.25, 0.5].into(),
+            scale: [0.25, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_image_region");
+}
+
+#[test]
+fn draw_animation() {
+    let mut ctx = common::headless_context();
+    let mut sheet = SpriteSheet::from_grid(happy_tree(&ctx), [128, 128]);
+    assert_eq!(sheet.frame_count(), 4);
+    sheet.add_animation("spin", Animation::uniform(0..4, 0.25, LoopMode::PingPong));
+    let mut player = AnimationPlayer::new(sheet);
+    player.play("spin");
+    // Halfway through the way back, on the third frame.
+    player.update(1.1);
+    assert_eq!(player.current_frame(), Some(2));
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    player.draw(
+        &mut render,
+        Transform {
+            position: [0.5, 0.5].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_animation");
+}
+
+#[test]
+fn draw_tint() {
+    let mut ctx = common::headless_context();
+    let image = happy_tree(&ctx);
+    let mut text = Text::new(&mut ctx);
+    text.add_text(["Tinted"]);
+    text.flush(&mut ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.25, 0.5].into(),
+            scale: [0.4, 0.4].into(),
+            color: [1.0, 0.2, 0.2, 1.0].into(),
+            ..Default::default()
+        },
+    );
+    image.draw(
+        &mut render,
+        Transform {
+            position: [0.75, 0.5].into(),
+            scale: [0.4, 0.4].into(),
+            color: [1.0, 1.0, 1.0, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    text.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            scale: [0.5, 0.5].into(),
+            color: [0.0, 1.0, 0.0, 1.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_tint");
+}
+
+#[test]
+fn draw_image_batch_ex() {
+    let mut ctx = common::headless_context();
+    let image_bytes = include_bytes!("../resources/textures/happy-tree.png");
+    let image_data = image::load_from_memory(image_bytes).unwrap();
+    let image_rgba = image_data.as_rgba8().unwrap();
+    let dimensions = image_data.dimensions();
+    let mut batch = ImageBatch::new(&ctx, [dimensions.0, dimensions.1], image_rgba).unwrap();
+    let instances = (0..4)
+        .map(|idx| BatchInstance {
+            transform: Transform {
+                position: [0.125 + idx as f32 * 0.25, 0.5].into(),
+                scale: [0.2, 0.2].into(),
+                ..Default::default()
+            },
+            src: Some(Rect::new(
+                (idx % 2) as f32 * 128.0,
+                (idx / 2) as f32 * 128.0,
+                128.0,
+                128.0,
+            )),
+            color: Some([1.0, 1.0 - idx as f32 * 0.25, 1.0, 1.0].into()),
+        })
+        .collect::<Vec<_>>();
+    batch.add_instance_ex(&mut ctx, &instances);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    batch.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    // The draw tint applies on top of the instance colors.
+    batch.draw(
+        &mut render,
+        Transform {
+            position: [0.0, 0.3].into(),
+            offset: [0.0, 0.0].into(),
+            color: [1.0, 0.5, 0.5, 1.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_image_batch_ex");
+}
+
+#[test]
+fn draw_image_batch_update() {
+    let mut ctx = common::headless_context();
+    let image_bytes = include_bytes!("../resources/textures/happy-tree.png");
+    let image_data = image::load_from_memory(image_bytes).unwrap();
+    let image_rgba = image_data.as_rgba8().unwrap();
+    let dimensions = image_data.dimensions();
+    let mut batch = ImageBatch::new(&ctx, [dimensions.0, dimensions.1], image_rgba).unwrap();
+    let row = |x: f32, y: f32| Transform {
+        position: [x, y].into(),
+        scale: [0.2, 0.2].into(),
+        ..Default::default()
+    };
+    batch.add_instance(
+        &mut ctx,
+        &[
+            row(0.2, 0.25),
+            row(0.4, 0.25),
+            row(0.6, 0.25),
+            row(0.8, 0.25),
+        ],
+    );
+    batch.set_instance(0, row(0.2, 0.75));
+    batch.set_instances(2..4, &[row(0.6, 0.75), row(0.8, 0.75)]);
+    // The last instance takes the place of the second.
+    batch.swap_remove(1);
+    assert_eq!(batch.len(), 3);
+    batch.flush(&mut ctx);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    batch.draw(
+        &mut render,
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_image_batch_update");
+}
+
+#[test]
+fn draw_natural_size() {
+    let mut ctx = common::headless_context();
+    let mut image = happy_tree(&ctx);
+    image.set_size_mode(oblivion::SizeMode::Natural);
+    // Changing the projection after creating the image must not change its size on screen.
+    ctx.set_projection([2.0, 2.0]);
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    image.draw(
+        &mut render,
+        Transform {
+            position: [1.0, 1.0].into(),
+            scale: [0.5, 0.5].into(),
+            ..Default::default()
+        },
+    );
+    image.draw_region(
+        &mut render,
+        Rect::new(0.0, 0.0, 64.0, 64.0),
+        Transform {
+            offset: [0.0, 0.0].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_natural_size");
+}
+
+#[test]
+fn draw_sampler() {
+    let mut ctx = common::headless_context();
+    let checker = [
+        255, 255, 255, 255, 0, 0, 0, 255, //
+        0, 0, 0, 255, 255, 255, 255, 255,
+    ];
+    let smooth = Image::with_options(
+        &ctx,
+        [2, 2],
+        &checker,
+        ImageOptions {
+            sampler: SamplerOptions::linear(),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut crisp = smooth.clone();
+    crisp.set_sampler(&ctx, SamplerOptions::nearest());
+    let mut tiled = smooth.clone();
+    tiled.set_sampler(
+        &ctx,
+        SamplerOptions::nearest().with_address_mode(wgpu::AddressMode::Repeat),
+    );
+    assert_eq!(smooth.sampler(), SamplerOptions::linear());
+    assert_eq!(crisp.sampler(), SamplerOptions::nearest());
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (idx, image) in [&smooth, &crisp].into_iter().enumerate() {
+        image.draw(
+            &mut render,
+            Transform {
+                position: [0.2 + idx as f32 * 0.3, 0.5].into(),
+                scale: [0.25, 0.25].into(),
+                ..Default::default()
+            },
+        );
+    }
+    tiled.draw_region(
+        &mut render,
+        Rect::new(0.0, 0.0, 8.0, 8.0),
+        Transform {
+            position: [0.8, 0.5].into(),
+            scale: [0.25, 0.25].into(),
+            ..Default::default()
+        },
+    );
+    check(&mut ctx, &render, "draw_sampler");
+}
+
+#[test]
+fn draw_mipmaps() {
+    let mut ctx = common::headless_context();
+    let plain = happy_tree(&ctx);
+    let mipmapped = happy_tree_with_options(
+        &ctx,
+        ImageOptions {
+            sampler: SamplerOptions::trilinear(),
+            generate_mipmaps: true,
+            ..Default::default()
+        },
+    );
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (idx, image) in [&plain, &mipmapped].into_iter().enumerate() {
+        image.draw(
+            &mut render,
+            Transform {
+                position: [0.25 + idx as f32 * 0.5, 0.5].into(),
+                scale: [0.05, 0.05].into(),
+                ..Default::default()
+            },
+        );
+    }
+    check(&mut ctx, &render, "draw_mipmaps");
+}
+
+#[test]
+fn draw_pixel_formats() {
+    let mut ctx = common::headless_context();
+    let image = |format, data: &[u8]| {
+        let options = ImageOptions {
+            format,
+            ..Default::default()
+        };
+        Image::with_options(&ctx, [2, 1], data, options).unwrap()
+    };
+    // Half floats, 1.0 is 0x3c00 and 0.5 is 0x3800.
+    let (one, half, zero) = ([0x00, 0x3c], [0x00, 0x38], [0x00, 0x00]);
+    let hdr = [one, half, zero, one, zero, half, one, one].concat();
+    let images = [
+        image(PixelFormat::R8, &[255, 64]),
+        image(
+            PixelFormat::Rgba8Unorm,
+            &[255, 128, 0, 255, 0, 128, 255, 255],
+        ),
+        image(PixelFormat::Rgba16Float, &hdr),
+        // Red and blue are kept equal, wgpu's GL backend uploads BGRA data as RGBA.
+        image(
+            PixelFormat::Bgra8UnormSrgb,
+            &[200, 0, 200, 255, 0, 255, 0, 128],
+        ),
+    ];
+
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    for (idx, image) in images.iter().enumerate() {
+        assert_eq!(
+            image.download_rgba(&mut ctx).unwrap().len(),
+            2 * image.format().bytes_per_pixel() as usize
+        );
+        image.draw(
+            &mut render,
+            Transform {
+                position: [0.125 + idx as f32 * 0.25, 0.5].into(),
+                scale: [0.2, 0.2].into(),
+                ..Default::default()
+            },
+        );
+    }
+    check(&mut ctx, &render, "draw_pixel_formats");
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use oblivion::{
+    Image, ImageBatch, ImageOptions, OblivionError, PixelFormat, Rect, Render, SamplerOptions,
+    TextureAtlasBuilder, Transform,
+};
+
+#[test]
+fn invalid_image_data() {
+    let ctx = common::headless_context();
+    let options = ImageOptions {
+        format: PixelFormat::Rgba16Float,
+        ..Default::default()
+    };
+    for (dimensions, len) in [([2, 2], 16), ([0, 2], 0)] {
+        match Image::with_options(&ctx, dimensions, &vec![0; len], options) {
+            Err(OblivionError::InvalidImageData { format, .. }) => {
+                assert_eq!(format, PixelFormat::Rgba16Float)
+            }
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("Invalid image data was accepted"),
+        }
+    }
+    assert!(ImageBatch::with_options(&ctx, [1, 1], &[0; 3], ImageOptions::default()).is_err());
+    assert!(Image::new(&ctx, [1, 1], &[0; 3]).is_err());
+    assert!(ImageBatch::new(&ctx, [1, 1], &[0; 3]).is_err());
+
+    let mut builder = TextureAtlasBuilder::new();
+    assert!(builder.add("empty", [0, 1], &[]).is_err());
+    assert!(builder.add("short", [1, 1], &[0; 3]).is_err());
+    assert!(builder.add("pixel", [1, 1], &[0; 4]).is_ok());
+}
+
+#[test]
+fn image_update() {
+    let mut ctx = common::headless_context();
+    let mut image = Image::new(&ctx, [3, 2], &[0; 3 * 2 * 4]).unwrap();
+    let frame = (0..3 * 2 * 4).map(|v| v as u8).collect::<Vec<_>>();
+    image.update(&ctx, &frame).unwrap();
+    assert_eq!(image.download_rgba(&mut ctx).unwrap(), frame);
+
+    image
+        .update_region(&ctx, Rect::new(1.0, 1.0, 2.0, 1.0), &[255; 2 * 4])
+        .unwrap();
+    let mut expected = frame;
+    expected[16..].fill(255);
+    assert_eq!(image.download_rgba(&mut ctx).unwrap(), expected);
+
+    assert!(matches!(
+        image.update_region(&ctx, Rect::new(2.0, 0.0, 2.0, 1.0), &[0; 2 * 4]),
+        Err(OblivionError::InvalidRegion(_))
+    ));
+    assert!(matches!(
+        image.update(&ctx, &[0; 4]),
+        Err(OblivionError::InvalidImageData { .. })
+    ));
+}
+
+#[test]
+fn sampler_anisotropy_is_sanitized() {
+    let mut ctx = common::headless_context();
+    let mut image = Image::new(&ctx, [1, 1], &[255; 4]).unwrap();
+    for sampler in [
+        SamplerOptions::nearest(),
+        SamplerOptions::linear(),
+        SamplerOptions::trilinear(),
+    ] {
+        for anisotropy in [0, 3, 12, 16, 255] {
+            image.set_sampler(
+                &ctx,
+                SamplerOptions {
+                    anisotropy,
+                    ..sampler
+                },
+            );
+            let mut render = Render::new();
+            image.draw(&mut render, Transform::default());
+            ctx.submit_render(&render).unwrap();
+        }
+    }
+}
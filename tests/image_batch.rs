@@ -0,0 +1,32 @@
+mod common;
+
+use oblivion::{ImageBatch, ImageOptions, Transform};
+
+#[test]
+fn image_batch_growth() {
+    let mut ctx = common::headless_context();
+    let mut batch = ImageBatch::with_capacity(
+        &mut ctx,
+        [1, 1],
+        &[255, 255, 255, 255],
+        ImageOptions::default(),
+        3,
+    )
+    .unwrap();
+    assert_eq!(batch.capacity(), 3);
+    for idx in 0..4 {
+        batch.add_instance(
+            &mut ctx,
+            &[Transform {
+                position: [idx as f32 * 0.25, 0.5].into(),
+                ..Default::default()
+            }],
+        );
+    }
+    assert_eq!(batch.capacity(), 6);
+    batch.clear();
+    batch.add_instance(&mut ctx, &[Transform::default()]);
+    batch.shrink_to_fit(&mut ctx);
+    assert_eq!(batch.capacity(), 1);
+    assert_eq!(batch.len(), 1);
+}
@@ -0,0 +1,81 @@
+//! Loading and saving images, requires the `image` feature.
+
+mod common;
+
+use common::{happy_tree, DIMENSIONS};
+use oblivion::{Canvas, Image, ImageOptions, OblivionError, PixelFormat, Render, Transform};
+
+#[test]
+fn load_image() {
+    let mut ctx = common::headless_context();
+    let image_bytes = include_bytes!("../resources/textures/happy-tree.png");
+    let from_bytes = Image::from_bytes(&ctx, image_bytes).unwrap();
+    let path = format!(
+        "{}/resources/textures/happy-tree.png",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let from_path = Image::from_path(&ctx, path).unwrap();
+    let rgba = from_bytes.download_rgba(&mut ctx).unwrap();
+    assert_eq!(rgba, happy_tree(&ctx).download_rgba(&mut ctx).unwrap());
+    assert_eq!(rgba, from_path.download_rgba(&mut ctx).unwrap());
+
+    // Grayscale images are converted to RGBA.
+    let gray =
+        image::DynamicImage::ImageLuma8(image::GrayImage::from_raw(2, 1, vec![0, 255]).unwrap());
+    let gray = Image::from_dynamic_image(&ctx, &gray).unwrap();
+    assert_eq!(
+        gray.download_rgba(&mut ctx).unwrap(),
+        [0, 0, 0, 255, 255, 255, 255, 255]
+    );
+
+    assert!(matches!(
+        Image::from_bytes(&ctx, b"not an image"),
+        Err(OblivionError::LoadImage(_))
+    ));
+    assert!(matches!(
+        Image::from_path(&ctx, "does/not/exist.png"),
+        Err(OblivionError::LoadImage(_))
+    ));
+}
+
+#[test]
+fn capture_and_save_png() {
+    let mut ctx = common::headless_context();
+    let mut render = Render::new();
+    oblivion::clear(&mut render, [0.1, 0.2, 0.3, 1.0]);
+    happy_tree(&ctx).draw(&mut render, Transform::default());
+    ctx.submit_render(&render).unwrap();
+    let frame = ctx.capture_frame().unwrap();
+    assert_eq!(frame.dimensions(), (DIMENSIONS[0], DIMENSIONS[1]));
+    assert_eq!(frame.into_raw(), ctx.download_screen_rgba().unwrap());
+
+    let dir = std::env::temp_dir();
+    let canvas = Canvas::new(&mut ctx, [16, 8]);
+    let mut render = Render::new();
+    oblivion::push_canvas(&mut render, &canvas);
+    oblivion::clear(&mut render, [1.0, 0.0, 0.0, 1.0]);
+    oblivion::pop_canvas(&mut render);
+    ctx.submit_render(&render).unwrap();
+    let canvas_path = dir.join("oblivion_save_canvas.png");
+    canvas.save_png(&mut ctx, &canvas_path).unwrap();
+    let saved = image::open(&canvas_path).unwrap().to_rgba8();
+    assert_eq!(saved.dimensions(), (16, 8));
+    assert_eq!(saved.get_pixel(0, 0).0, [255, 0, 0, 255]);
+
+    let mask = Image::with_options(
+        &ctx,
+        [2, 1],
+        &[0, 200],
+        ImageOptions {
+            format: PixelFormat::R8,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mask_path = dir.join("oblivion_save_mask.png");
+    mask.save_png(&mut ctx, &mask_path).unwrap();
+    assert_eq!(
+        image::open(&mask_path).unwrap().to_luma8().into_raw(),
+        [0, 200]
+    );
+}
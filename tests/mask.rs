@@ -0,0 +1,34 @@
+mod common;
+
+use oblivion::{MeshBuilder, Render, Transform};
+
+#[test]
+fn clear_keeps_active_mask() {
+    let mut ctx = common::headless_context();
+    let quad = |size: [f32; 2], color: [f32; 4]| {
+        MeshBuilder::new()
+            .quad([0.0, 0.0], size, color, oblivion::DrawMode::fill())
+            .unwrap()
+            .build(&ctx)
+    };
+    let left_half = quad([0.5, 1.0], [1.0, 1.0, 1.0, 1.0]);
+    let full = quad([1.0, 1.0], [1.0, 0.0, 0.0, 1.0]);
+    let top_left = Transform {
+        offset: [0.0, 0.0].into(),
+        ..Default::default()
+    };
+
+    let mut render = Render::new();
+    oblivion::push_mask(&mut render, &left_half, top_left);
+    full.draw(&mut render, top_left);
+    oblivion::clear(&mut render, [0.0, 0.0, 1.0, 1.0]);
+    full.draw(&mut render, top_left);
+    oblivion::pop_mask(&mut render);
+    ctx.submit_render(&render).unwrap();
+
+    let rgba = ctx.download_screen_rgba().unwrap();
+    let width = common::DIMENSIONS[0] as usize;
+    let pixel = |x: usize, y: usize| &rgba[(y * width + x) * 4..][..4];
+    assert_eq!(pixel(10, 90), [255, 0, 0, 255]);
+    assert_eq!(pixel(width - 10, 90), [0, 0, 255, 255]);
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use oblivion::{Canvas, OblivionError, Render};
+
+#[test]
+fn dropped_canvas_is_stale() {
+    let mut ctx = common::headless_context();
+    let canvas = Canvas::new(&mut ctx, [64, 64]);
+    let mut render = Render::new();
+    oblivion::push_canvas(&mut render, &canvas);
+    oblivion::clear(&mut render, [1.0, 1.0, 1.0, 1.0]);
+    oblivion::pop_canvas(&mut render);
+    ctx.submit_render(&render).unwrap();
+
+    drop(canvas);
+    // The freed slot is reused, the old handle must not resolve to the new canvas.
+    let _replacement = Canvas::new(&mut ctx, [32, 32]);
+    assert!(matches!(
+        ctx.submit_render(&render),
+        Err(OblivionError::StaleHandle)
+    ));
+}